@@ -0,0 +1,66 @@
+use crate::config::PrecisionPolicy;
+use crate::errors::EngineError;
+use crate::money::Money;
+use csv::Writer;
+use std::fs::File;
+use std::io::Write;
+
+/*
+    A row whose amount was rounded or truncated by the precision policy and then applied. It is
+    kept apart from the rejections, the row did change the balances, but the reconciliation team
+    still has to see which rows were applied with another amount than the one that was sent.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Adjustment {
+    pub file: Option<String>,
+    pub line: u64,
+    // The row as it was read, encoded as one csv line
+    pub record: String,
+    pub tx: u32,
+    pub client: u16,
+    // The amount as it was written in the row
+    pub amount: String,
+    // The amount that was applied
+    pub adjusted: Money,
+    pub policy: PrecisionPolicy,
+}
+
+/*
+    Machine readable report of every amount that was rounded or truncated, written as csv with the
+    columns file, line, record, tx, client, amount, adjusted, policy. file is left empty for input
+    that isn't a file, amount is the amount as it was written and adjusted the one that was applied.
+ */
+pub struct AdjustmentsWriter {
+    writer: Writer<Box<dyn Write + Send>>,
+}
+
+impl AdjustmentsWriter {
+    pub fn new(output: Box<dyn Write + Send>) -> Result<Self, EngineError> {
+        let mut writer = Writer::from_writer(output);
+        writer.write_record(["file", "line", "record", "tx", "client", "amount", "adjusted", "policy"])?;
+        Ok(Self { writer })
+    }
+
+    pub fn from_path(path: &str) -> Result<Self, EngineError> {
+        Self::new(Box::new(File::create(path)?))
+    }
+
+    pub fn write(&mut self, adjustment: &Adjustment) -> Result<(), EngineError> {
+        self.writer.write_record([
+            adjustment.file.clone().unwrap_or_default(),
+            adjustment.line.to_string(),
+            adjustment.record.clone(),
+            adjustment.tx.to_string(),
+            adjustment.client.to_string(),
+            adjustment.amount.clone(),
+            adjustment.adjusted.to_string(),
+            adjustment.policy.to_string(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), EngineError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -1,28 +1,73 @@
-use serde::{Serialize};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use log::{info, warn};
+use log::{info};
+use crate::config::DisputePolicy;
+use crate::errors::EngineError;
+use crate::money::Money;
+use crate::transactions::EntryKind;
 
-#[derive(Debug, Serialize)]
+/*
+    One client's account. Outside the crate it is a read-only view, balances only change through
+    the engine. A client can't be deserialized from outside either, snapshots are loaded through
+    ClientState.
+ */
+#[derive(Debug, Clone, Serialize)]
 pub struct Client {
     id: u16,
-    available: f32,
-    held: f32,
-    total: f32,
+    available: Money,
+    held: Money,
+    total: Money,
     locked: bool,
-    disputed_transactions: HashMap<u32, f32>,
+    disputed_transactions: HashMap<u32, DisputedTransaction>,
+}
+
+/*
+    The fields of a Client as they are written in a snapshot, read back by load_state
+ */
+#[derive(Deserialize)]
+pub(crate) struct ClientState {
+    id: u16,
+    available: Money,
+    held: Money,
+    total: Money,
+    locked: bool,
+    disputed_transactions: HashMap<u32, DisputedTransaction>,
+}
+
+impl From<ClientState> for Client {
+    fn from(state: ClientState) -> Self {
+        Self {
+            id: state.id,
+            available: state.available,
+            held: state.held,
+            total: state.total,
+            locked: state.locked,
+            disputed_transactions: state.disputed_transactions,
+        }
+    }
+}
+
+/*
+    A transaction under dispute. The kind is kept with the amount because the direction the funds
+    move on resolve and chargeback depends on whether a deposit or a withdrawal was disputed.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisputedTransaction {
+    pub kind: EntryKind,
+    pub amount: Money,
 }
 
 impl Client {
-    pub fn new(client_id: u16) -> Self {
+    pub(crate) fn new(client_id: u16) -> Self {
         Self {
             id: client_id,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
+            available: Money::ZERO,
+            held: Money::ZERO,
+            total: Money::ZERO,
             locked: false,
             // Each client instance will keep track of its disputes. Reduces the number of times the
             // master transaction map has to be accessed
-            // Key: tx_id, Value: tx amount
+            // Key: tx_id, Value: kind and amount of the disputed tx
             disputed_transactions: HashMap::new(),
         }
     }
@@ -31,35 +76,37 @@ impl Client {
         self.id
     }
 
-    pub fn available(&self) -> f32 {
+    pub fn available(&self) -> Money {
         self.available
     }
 
-    pub fn set_available(&mut self, amount: f32) {
-        self.available += amount;
-        self.total += amount;
-    }
-
-
-    pub fn set_held(&mut self, amount: f32)  {
-        self.held += amount;
-        self.available -= amount;
-
+    /*
+    Description: Raw setter that moves funds from available to held without the dispute
+                 bookkeeping, it leaves the client in a state that breaks its invariants. Only
+                 tests use it, to build such a state on purpose.
+    */
+    #[cfg(test)]
+    pub(crate) fn set_held(&mut self, amount: Money) -> Result<(), EngineError> {
+        let held = self.held.checked_add(amount)?;
+        let available = self.available.checked_sub(amount)?;
+        self.held = held;
+        self.available = available;
+        Ok(())
     }
 
     pub fn locked(&self) -> bool {
         self.locked
     }
 
-    pub fn set_locked(&mut self, status: bool) {
+    pub(crate) fn set_locked(&mut self, status: bool) {
         self.locked = status;
     }
 
-    pub fn held(&self) -> f32 {
+    pub fn held(&self) -> Money {
         self.held
     }
 
-    pub fn total(&self) -> f32 {
+    pub fn total(&self) -> Money {
         self.total
     }
 
@@ -71,7 +118,7 @@ impl Client {
       transaction_type: &str  Type of transaction attempted
       tx: u32 id of transaction
   */
-    pub fn is_account_locked(&self, tx: u32) -> bool {
+    pub(crate) fn is_account_locked(&self, tx: u32) -> bool {
         if self.locked {
             log::warn!(
             "Skipping transaction {}: Account {} is locked.",
@@ -82,35 +129,59 @@ impl Client {
         }
         false
     }
-    pub fn disputed_transactions(&self) -> &HashMap<u32, f32> {
+    pub fn disputed_transactions(&self) -> &HashMap<u32, DisputedTransaction> {
         &self.disputed_transactions
     }
 
     /*
-    Description: Modifies client instance by adding to available and total funds in their account
-    Parameters:
-        amount: Option<f32> The amount to be deposited into the account
+    Description: Checks the rules every account has to follow after every operation:
+                 available + held == total, held is never negative and held is exactly the sum of
+                 the open disputes. available itself may be negative when disputes are allowed to
+                 drive it below zero.
+    Returns: which rule is broken
     */
-    pub fn deposit(&mut self, amount: Option<f32>) {
-        match amount {
-            Some(value) if value > 0.0 => {
-                self.available += value;
-                self.total += value;
-                info!(
-                    "Deposited ${:.4} to Client {}. New available balance: ${:.4}",
-                    value, self.id, self.available
-                );
-            }
-            Some(0.0) => {
-                warn!("Cannot deposit a zero amount: $0.00 is not allowed");
-            }
-            Some(_value) => {
-                warn!("Cannot deposit a negative amount of money");
-            }
-            None => {
-                warn!("Deposit failed: Amount is None");
-            }
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let total = self.available.checked_add(self.held).map_err(|err| err.to_string())?;
+        if total != self.total {
+            return Err(format!(
+                "available {} + held {} != total {}",
+                self.available, self.held, self.total
+            ));
+        }
+        if self.held.is_negative() {
+            return Err(format!("held {} is negative", self.held));
+        }
+        let mut disputed = Money::ZERO;
+        for dispute in self.disputed_transactions.values() {
+            disputed = disputed.checked_add(dispute.amount).map_err(|err| err.to_string())?;
         }
+        if disputed != self.held {
+            return Err(format!("held {} != open disputes {}", self.held, disputed));
+        }
+        Ok(())
+    }
+
+    /*
+    Description: Modifies client instance by adding to available and total funds in their account.
+                 A deposit that would overflow either balance is rejected and leaves the account
+                 untouched.
+    Parameters:
+        amount: Option<Money> The amount to be deposited into the account
+    */
+    pub(crate) fn deposit(&mut self, amount: Option<Money>) -> Result<(), EngineError> {
+        let value = match amount {
+            Some(value) if value.is_positive() => value,
+            _ => return Err(EngineError::InvalidAmount),
+        };
+        let available = self.available.checked_add(value)?;
+        let total = self.total.checked_add(value)?;
+        self.available = available;
+        self.total = total;
+        info!(
+            "Deposited ${} to Client {}. New available balance: ${}",
+            value, self.id, self.available
+        );
+        Ok(())
     }
 
     /*
@@ -118,110 +189,156 @@ impl Client {
         Modifies client instance by reducing the available and total funds in their account.
         If the withdrawal amount is greater than the available funds, result in error
     Parameters:
-        amount: Option<f32> The amount to be deposited into the account
+        amount: Option<Money> The amount to be withdrawn from the account
     */
-    pub fn withdraw(&mut self, amount: Option<f32>) -> Result<(), &str> {
-        match amount {
-            Some(value) if value > 0.0 => {
-                if self.available >= value {
-                    self.available -= value;
-                    self.total -= value;
-                    info!(
-                        "Withdrawal of ${:.4} successful. Your new balance is: ${:.4}",
-                        value, self.available
-                    );
-                    Ok(())
-                } else {
-                    Err("Insufficient funds")
-                }
-            }
-            Some(_value) => {
-                println!("Cannot withdraw a negative or zero amount");
-                Err("Invalid withdrawal amount")
-            }
-            None => {
-                warn!("Withdrawal failed: None value found");
-                Err("No amount provided for withdrawal")
-            }
+    pub(crate) fn withdraw(&mut self, amount: Option<Money>) -> Result<(), EngineError> {
+        let value = match amount {
+            Some(value) if value.is_positive() => value,
+            _ => return Err(EngineError::InvalidAmount),
+        };
+        if self.available < value {
+            return Err(EngineError::InsufficientFunds);
         }
+        let available = self.available.checked_sub(value)?;
+        let total = self.total.checked_sub(value)?;
+        self.available = available;
+        self.total = total;
+        info!(
+            "Withdrawal of ${} successful. Your new balance is: ${}",
+            value, self.available
+        );
+        Ok(())
     }
 
 
     /*
-    Description: Disputes a transaction, adding the transaction in question to the clients hashmap
+    Description: Administrative correction of the account. The signed amount is added to available
+                 and total, held is left alone so available + held == total still holds. A debit
+                 that would take available below zero is rejected. A credit is always allowed, it
+                 may only bring a balance that a dispute took negative closer to zero.
     Parameters:
-        tx_id: u32 The transaction id of the tx in question
-        amount: Option<f32> The amount to be deposited into the account
-    NOTE:
-        Currently this function only disputes withdrawals, but there could be a case where a
-        deposit should be disputed. Will adjust in the future to handle this
+        amount: Money The signed correction, it can't be zero
+    */
+    pub(crate) fn adjust(&mut self, amount: Money) -> Result<(), EngineError> {
+        if amount == Money::ZERO {
+            return Err(EngineError::InvalidAmount);
+        }
+        let available = self.available.checked_add(amount)?;
+        if amount.is_negative() && available.is_negative() {
+            return Err(EngineError::InsufficientFunds);
+        }
+        let total = self.total.checked_add(amount)?;
+        self.available = available;
+        self.total = total;
+        Ok(())
+    }
 
+    /*
+    Description: Disputes a transaction, adding the transaction in question to the clients hashmap.
+                 The direction of the funds depends on what is being disputed:
+                 Deposit:    the deposited funds are moved from available to held
+                 Withdrawal: the withdrawn funds are held pending a reversal credit, held and total
+                             increase while available is left alone
+    Parameters:
+        tx_id: u32 The transaction id of the tx in question
+        kind: EntryKind Whether the disputed tx was a deposit or a withdrawal
+        amount: Option<Money> The amount of the disputed transaction
+        policy: DisputePolicy Whether a disputed deposit may drive available below zero
     */
-    pub fn dispute(&mut self, tx_id: u32, amount: Option<f32>) -> Result<(), &str> {
-        match amount {
-            Some(value) if value > 0.0 => {
-                if self.available >= value {
-                    self.available -= value;
-                    self.held += value;
-                    self.disputed_transactions.insert(tx_id, value);
-                    info!(
-                        "Dispute initiated for amount ${:.4} on transaction {}. Held: ${:.4}, Available: ${:.4}",
-                        value, tx_id, self.held, self.available
-                    );
-                    Ok(())
-                } else {
-                    Err("Insufficient available funds for dispute")
+    pub(crate) fn dispute(&mut self, tx_id: u32, kind: EntryKind, amount: Option<Money>, policy: DisputePolicy) -> Result<(), EngineError> {
+        let value = match amount {
+            Some(value) if value.is_positive() => value,
+            _ => return Err(EngineError::InvalidAmount),
+        };
+        if self.disputed_transactions.contains_key(&tx_id) {
+            return Err(EngineError::AlreadyDisputed { tx: tx_id });
+        }
+        let held = self.held.checked_add(value)?;
+        match kind {
+            EntryKind::Deposit => {
+                if self.available < value && policy == DisputePolicy::Reject {
+                    return Err(EngineError::InsufficientFunds);
                 }
+                self.available = self.available.checked_sub(value)?;
+            }
+            EntryKind::Withdrawal => {
+                self.total = self.total.checked_add(value)?;
             }
-            Some(_) => Err("Invalid dispute amount"),
-            None => Err("No amount provided for dispute"),
         }
+        self.held = held;
+        self.disputed_transactions.insert(tx_id, DisputedTransaction { kind, amount: value });
+        info!(
+            "Dispute initiated for {:?} of ${} on transaction {}. Held: ${}, Available: ${}",
+            kind, value, tx_id, self.held, self.available
+        );
+        Ok(())
     }
 
     /*
     Description:
-        Undoes a dispute by removing it from the clients map. Held funds are transferred to the
-        available field
+        Undoes a dispute by removing it from the clients map. The original transaction stands:
+        Deposit:    held funds are transferred back to the available field
+        Withdrawal: the held reversal credit is dropped from held and total
     Parameters:
         tx_id: u32 The id of the transaction being disputed
     */
-    pub fn resolve(&mut self, tx_id: u32) -> Result<(), &str> {
-        if let Some(&amount) = self.disputed_transactions.get(&tx_id) {
-            if self.held >= amount {
-                self.held -= amount;
-                self.available += amount;
-                self.disputed_transactions.remove(&tx_id); // Remove the resolved transaction
-                info!(
-                    "Resolved dispute for transaction {}: Held -= {:.2}, Available += {:.2}",
-                    tx_id, amount, amount
-                );
-                Ok(())
-            } else {
-                Err("Insufficient held funds to resolve dispute")
+    pub(crate) fn resolve(&mut self, tx_id: u32) -> Result<(), EngineError> {
+        let Some(&disputed) = self.disputed_transactions.get(&tx_id) else {
+            return Err(EngineError::NotDisputed { tx: tx_id });
+        };
+        if self.held < disputed.amount {
+            return Err(EngineError::InsufficientFunds);
+        }
+        let held = self.held.checked_sub(disputed.amount)?;
+        match disputed.kind {
+            EntryKind::Deposit => {
+                self.available = self.available.checked_add(disputed.amount)?;
             }
-        } else {
-            Err("Transaction not found in disputed transactions")
-        }
-    }
-
-    pub fn chargeback(&mut self, tx_id: u32) -> Result<(), &str> {
-        if let Some(&disputed_amount) = self.disputed_transactions.get(&tx_id) {
-            if self.held >= disputed_amount {
-                self.held -= disputed_amount;
-                self.total -= disputed_amount;
-                self.disputed_transactions.remove(&tx_id);
-                self.locked = true;
-
-                info!(
-                    "Chargeback processed for transaction {}: Held -= {:.4}, Total -= {:.4}. Account is locked.",
-                    tx_id, disputed_amount, disputed_amount
-                );
-                Ok(())
-            } else {
-                Err("Insufficient held funds for chargeback")
+            EntryKind::Withdrawal => {
+                self.total = self.total.checked_sub(disputed.amount)?;
             }
-        } else {
-            Err("Transaction not found in disputed transactions for chargeback")
         }
+        self.held = held;
+        self.disputed_transactions.remove(&tx_id); // Remove the resolved transaction
+        info!(
+            "Resolved dispute on {:?} {}: Held -= {}. Held: ${}, Available: ${}, Total: ${}",
+            disputed.kind, tx_id, disputed.amount, self.held, self.available, self.total
+        );
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /*
+    Description:
+        Final step of a dispute, the original transaction is reversed and the account is locked.
+        Deposit:    held funds are removed from the account, held and total decrease
+        Withdrawal: the held reversal credit is released to the client, held moves to available
+    Parameters:
+        tx_id: u32 The id of the transaction being charged back
+    */
+    pub(crate) fn chargeback(&mut self, tx_id: u32) -> Result<(), EngineError> {
+        let Some(&disputed) = self.disputed_transactions.get(&tx_id) else {
+            return Err(EngineError::NotDisputed { tx: tx_id });
+        };
+        if self.held < disputed.amount {
+            return Err(EngineError::InsufficientFunds);
+        }
+        let held = self.held.checked_sub(disputed.amount)?;
+        match disputed.kind {
+            EntryKind::Deposit => {
+                self.total = self.total.checked_sub(disputed.amount)?;
+            }
+            EntryKind::Withdrawal => {
+                self.available = self.available.checked_add(disputed.amount)?;
+            }
+        }
+        self.held = held;
+        self.disputed_transactions.remove(&tx_id);
+        self.locked = true;
+
+        info!(
+            "Chargeback processed for {:?} {}: Held -= {}. Available: ${}, Total: ${}. Account is locked.",
+            disputed.kind, tx_id, disputed.amount, self.available, self.total
+        );
+        Ok(())
+    }
+}
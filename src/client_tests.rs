@@ -1,4 +1,8 @@
 use crate::client::Client;
+use crate::config::{DisputePolicy, EngineConfig};
+use crate::errors::EngineError;
+use crate::money::Money;
+use crate::transactions::EntryKind;
 use crate::transactions::*;
 use test_case::test_case;
 use std::sync::Once;
@@ -39,38 +43,33 @@ fn logger(log_file: &str) {
 /* Using test-case crate for unit tests for client functions
    Each test case is enumerated per unit test
    Each line that starts with #[test_case] is calling the following function, but with
-   varying parameters being passed. The last parameter is the expected log output.
-   In the future more robust errors and checking will be added but for now this gets
-   the job done.
+   varying parameters being passed. The last parameter is the expected outcome returned by the
+   engine, so failures are matched on their EngineError instead of the log output.
 
    I.   Account is locked
    II.  Valid deposit amount
    III. Invalid deposit of $0
    V.   Valid deposit 4 places past decimal
  */
-#[test_case(1, 100.0, true, "Skipping transaction 1: Account 1 is locked.")]
-#[test_case(1, 100.0, false, "Deposit of $100.00 successful for client 1.")]
-#[test_case(1, 0.0, false, "Cannot deposit a zero or negative amount of money")]
-#[test_case(1, 100.1234, false, "Deposit of $100.1234 successful for client 1.")]
-#[allow(dead_code, unused_variables, unused_mut)]
-fn test_process_deposit(tx: u32, amount: f32, locked: bool, expected_log: &str) {
+#[test_case(1, "100.0", true, Err(EngineError::AccountLocked { client: 1 }))]
+#[test_case(1, "100.0", false, Ok(()))]
+#[test_case(1, "0.0", false, Err(EngineError::InvalidAmount))]
+#[test_case(1, "100.1234", false, Ok(()))]
+fn test_process_deposit(tx: u32, amount: &str, locked: bool, expected: Result<(), EngineError>) {
+    let amount: Money = amount.parse().unwrap();
     logger("client_test.log");
     // TODO: Tried to make the log skip a line when a new test is run for better readability but
     //       it's not writing to the file the way I want :(. Adjust fern settings to fix this.
     info!("\nTest: test_process_deposit");
     let mut client = Client::new(1);
     client.set_locked(locked);
-    let transaction = Transaction {
-        transaction_type: "deposit".to_string(),
-        client: 1,
-        tx,
-        amount: Some(amount),
-    };
+    let transaction = Transaction::builder(TransactionKind::Deposit, 1, tx).amount(amount).build();
 
-    process_deposit(&mut client, &transaction);
+    let engine = PaymentsEngine::new();
+    assert_eq!(engine.process_deposit(&mut client, &transaction), expected);
 
-    if locked {
-        assert_eq!(client.available(), 0.0);
+    if expected.is_err() {
+        assert_eq!(client.available(), Money::ZERO);
     } else {
         assert_eq!(client.available(), amount);
     }
@@ -79,124 +78,291 @@ fn test_process_deposit(tx: u32, amount: f32, locked: bool, expected_log: &str)
 /*
    I.   Account is locked
    II.  Valid withdrawal amount
-   III. Invalid negative withdrawal
+   III. Invalid withdrawal of $0
+   IV.  Withdrawal exceeds available funds
    V.   Valid withdrawal 4 places past decimal
  */
-#[test_case(1, 50.0, 100.0, true, "Skipping transaction 1: Account 1 is locked.")]
-#[test_case(1, 50.0, 100.0, false, "Withdrawal of $50.00 successful for client 1.")]
-#[test_case(1, 0.0, 50.0, false, "Cannot withdraw a non-positive amount")]
-#[test_case(1, 50.1234, 100.0, false, "Withdrawal of $50.1234 successful for client 1.")]
-#[allow(dead_code, unused_variables, unused_mut)]
-fn test_process_withdrawal(tx: u32, withdrawal_amount: f32, initial_balance: f32, locked: bool, expected_log: &str) {
+#[test_case(1, "50.0", "100.0", true, Err(EngineError::AccountLocked { client: 1 }))]
+#[test_case(1, "50.0", "100.0", false, Ok(()))]
+#[test_case(1, "0.0", "50.0", false, Err(EngineError::InvalidAmount))]
+#[test_case(1, "150.0", "100.0", false, Err(EngineError::InsufficientFunds))]
+#[test_case(1, "50.1234", "100.0", false, Ok(()))]
+fn test_process_withdrawal(tx: u32, withdrawal_amount: &str, initial_balance: &str, locked: bool, expected: Result<(), EngineError>) {
+    let withdrawal_amount: Money = withdrawal_amount.parse().unwrap();
+    let initial_balance: Money = initial_balance.parse().unwrap();
     logger("client_test.log");
     info!("\nTest: test_process_withdrawal");
     let mut client = Client::new(1);
-    client.deposit(Some(initial_balance));
+    client.deposit(Some(initial_balance)).unwrap();
     client.set_locked(locked);
 
-    let transaction = Transaction {
-        transaction_type: "withdrawal".to_string(),
-        client: 1,
-        tx,
-        amount: Some(withdrawal_amount),
-    };
+    let transaction = Transaction::builder(TransactionKind::Withdrawal, 1, tx).amount(withdrawal_amount).build();
 
-    process_withdrawal(&mut client, &transaction);
+    let engine = PaymentsEngine::new();
+    assert_eq!(engine.process_withdrawal(&mut client, &transaction), expected);
 
-    if locked {
+    if expected.is_err() {
         assert_eq!(client.available(), initial_balance);
     } else {
-        assert_eq!(client.available(), initial_balance - withdrawal_amount);
+        assert_eq!(client.available(), initial_balance.checked_sub(withdrawal_amount).unwrap());
     }
 }
 
 /*
    I.   Account is locked
    II.  Transaction is able to be successfully put under a dispute
+   III. Transaction is not in the engine's history
  */
-#[test_case(1, 50.0, true, "Skipping transaction 1: Account 1 is locked.")]
-#[test_case(1, 50.0, false, "Dispute successful for client 1.")]
-#[allow(dead_code, unused_variables, unused_mut)]
-fn test_process_dispute(tx: u32, amount: f32, locked: bool, expected_log: &str) {
+#[test_case(1, "50.0", true, Err(EngineError::AccountLocked { client: 1 }))]
+#[test_case(1, "50.0", false, Ok(()))]
+#[test_case(2, "50.0", false, Err(EngineError::UnknownTx { tx: 2 }))]
+fn test_process_dispute(tx: u32, amount: &str, locked: bool, expected: Result<(), EngineError>) {
+    let amount: Money = amount.parse().unwrap();
     logger("client_test.log");
-    info!("\nTest: test_process_withdrawal");
+    info!("\nTest: test_process_dispute");
+    let engine = PaymentsEngine::new();
     let mut client = Client::new(1);
-    let mut transaction = Transaction {
-        transaction_type: "dispute".to_string(),
-        client: 1,
-        tx,
-        amount: Some(amount),
-    };
-
-    client.deposit(Some(amount));
-    if !locked {
-        client.dispute(tx, Some(amount)).unwrap();
-    }
+    let deposit = Transaction::builder(TransactionKind::Deposit, 1, 1).amount(amount).build();
+    engine.process_deposit(&mut client, &deposit).unwrap();
+    client.set_locked(locked);
+
+    let transaction = Transaction::builder(TransactionKind::Dispute, 1, tx).build();
 
-    process_dispute(&mut client, &transaction);
+    assert_eq!(engine.process_dispute(&mut client, &transaction), expected);
 
-    if locked {
-        assert_eq!(client.held(), 0.0);
+    if expected.is_err() {
+        assert_eq!(client.held(), Money::ZERO);
     } else {
         assert_eq!(client.held(), amount);
     }
 }
 
+/*
+   Deposits the same amount twice through the engine (tx 1 and tx 2) and disputes tx 1
+ */
+fn dispute_first_of_two_deposits(engine: &PaymentsEngine, client: &mut Client, amount: Money) {
+    for (tx, transaction_type) in [(1, "deposit"), (2, "deposit"), (1, "dispute")] {
+        let transaction = Transaction::builder(transaction_type.parse().unwrap(), 1, tx).amount(amount).build();
+        match transaction_type {
+            "deposit" => engine.process_deposit(client, &transaction).unwrap(),
+            _ => engine.process_dispute(client, &transaction).unwrap(),
+        }
+    }
+}
+
 /*
    I.   Account is locked
    II.  Transaction is able to be successfully resolved
+   III. Transaction is not under dispute
  */
-#[test_case(1, 50.0, true, "Skipping transaction 1: Account 1 is locked.")]
-#[test_case(1, 50.0, false, "Transaction 1 resolved for client 1.")]
-#[allow(dead_code, unused_variables, unused_mut)]
-fn test_process_resolve(tx: u32, amount: f32, locked: bool, expected_log: &str) {
+#[test_case(1, "50.0", true, Err(EngineError::AccountLocked { client: 1 }))]
+#[test_case(1, "50.0", false, Ok(()))]
+#[test_case(2, "50.0", false, Err(EngineError::NotDisputed { tx: 2 }))]
+fn test_process_resolve(tx: u32, amount: &str, locked: bool, expected: Result<(), EngineError>) {
+    let amount: Money = amount.parse().unwrap();
     logger("client_test.log");
+    let engine = PaymentsEngine::new();
     let mut client = Client::new(1);
-    client.deposit(Some(amount));
-    client.dispute(tx, Some(amount)).unwrap();
+    dispute_first_of_two_deposits(&engine, &mut client, amount);
     client.set_locked(locked);
 
-    let transaction = Transaction {
-        transaction_type: "resolve".to_string(),
-        client: 1,
-        tx,
-        amount: None,
-    };
+    let transaction = Transaction::builder(TransactionKind::Resolve, 1, tx).build();
 
-    process_resolve(&mut client, &transaction);
+    assert_eq!(engine.process_resolve(&mut client, &transaction), expected);
 
-    if locked {
+    if expected.is_err() {
         assert_eq!(client.held(), amount);
     } else {
-        assert_eq!(client.available(), amount);
+        assert_eq!(client.available(), amount.checked_add(amount).unwrap());
     }
 }
 
 /*
    I.   Account is locked
    II.  Transaction is able to be successfully chargedback
+   III. Transaction is not under dispute
  */
-#[test_case(1, 50.0, true, "Skipping transaction 1: Account 1 is locked.")]
-#[test_case(1, 50.0, false, "Transaction 1 chargedback for client 1.")]
-#[allow(dead_code, unused_variables, unused_mut)]
-fn test_process_chargeback(tx: u32, amount: f32, locked: bool, expected_log: &str) {
+#[test_case(1, "50.0", true, Err(EngineError::AccountLocked { client: 1 }))]
+#[test_case(1, "50.0", false, Ok(()))]
+#[test_case(2, "50.0", false, Err(EngineError::NotDisputed { tx: 2 }))]
+fn test_process_chargeback(tx: u32, amount: &str, locked: bool, expected: Result<(), EngineError>) {
+    let amount: Money = amount.parse().unwrap();
     logger("client_test.log");
+    let engine = PaymentsEngine::new();
     let mut client = Client::new(1);
-    client.deposit(Some(amount));
-    client.dispute(tx, Some(amount)).unwrap();
+    dispute_first_of_two_deposits(&engine, &mut client, amount);
+    client.set_locked(locked);
 
-    let transaction = Transaction {
-        transaction_type: "chargeback".to_string(),
-        client: 1,
-        tx,
-        amount: None,
-    };
+    let transaction = Transaction::builder(TransactionKind::Chargeback, 1, tx).build();
 
-    process_chargeback(&mut client, &transaction);
+    assert_eq!(engine.process_chargeback(&mut client, &transaction), expected);
 
-    if locked {
-        assert_eq!(client.locked(), true);
+    if expected.is_err() {
+        assert_eq!(client.held(), amount);
     } else {
         assert!(client.locked());
+        assert_eq!(client.total(), amount);
+    }
+}
+
+/*
+   Client starts with 50.0 available
+   I.    Admin rows are not allowed, the lock stays
+   II.   Unlock clears the lock
+   III.  Lock sets it
+   IV.   Positive adjustment, allowed on a locked account
+   V.    Negative adjustment
+   VI.   Negative adjustment larger than available
+   VII.  Adjustment without a reason
+   VIII. Adjustment of zero
+ */
+#[test_case(false, "unlock", None, None, true, Err(EngineError::Unauthorized { kind: TransactionKind::Unlock }), "50", true)]
+#[test_case(true, "unlock", None, None, true, Ok(()), "50", false)]
+#[test_case(true, "LOCK", None, None, false, Ok(()), "50", true)]
+#[test_case(true, "adjust", Some("10.5"), Some("goodwill credit"), true, Ok(()), "60.5", true)]
+#[test_case(true, "adjust", Some("-20"), Some("duplicate payout"), false, Ok(()), "30", false)]
+#[test_case(true, "adjust", Some("-60"), Some("duplicate payout"), false, Err(EngineError::InsufficientFunds), "50", false)]
+#[test_case(true, "adjust", Some("5"), None, false, Err(EngineError::MissingReason), "50", false)]
+#[test_case(true, "adjust", Some("0"), Some("nothing"), false, Err(EngineError::InvalidAmount), "50", false)]
+#[allow(clippy::too_many_arguments)]
+fn test_process_admin(allow_admin: bool, transaction_type: &str, amount: Option<&str>, reason: Option<&str>, locked: bool, expected: Result<(), EngineError>, available: &str, expected_locked: bool) {
+    logger("client_test.log");
+    let engine = PaymentsEngine::with_config(EngineConfig { allow_admin, ..EngineConfig::default() });
+    let mut client = Client::new(1);
+    client.deposit(Some("50".parse().unwrap())).unwrap();
+    client.set_locked(locked);
+
+    let mut builder = Transaction::builder(transaction_type.parse().unwrap(), 1, 7);
+    if let Some(amount) = amount {
+        builder = builder.amount(amount.parse().unwrap());
     }
-}
\ No newline at end of file
+    if let Some(reason) = reason {
+        builder = builder.reason(reason);
+    }
+    let transaction = builder.build();
+
+    assert_eq!(engine.process_admin(&mut client, &transaction), expected);
+    assert_eq!(client.available(), available.parse::<Money>().unwrap());
+    assert_eq!(client.total(), client.available().checked_add(client.held()).unwrap());
+    assert_eq!(client.locked(), expected_locked);
+}
+
+/*
+   Adjustments on a balance that a dispute took negative under allow-negative. Deposit 100, withdraw
+   80 and dispute the deposit leaves available at -80.
+   I.    A goodwill credit that leaves available negative is applied
+   II.   A credit that brings available back above zero
+   III.  A debit is refused while available is negative
+ */
+#[test_case("50", Ok(()), "-30")]
+#[test_case("100", Ok(()), "20")]
+#[test_case("-5", Err(EngineError::InsufficientFunds), "-80")]
+fn test_adjust_negative_balance(amount: &str, expected: Result<(), EngineError>, available: &str) {
+    let mut client = Client::new(1);
+    client.deposit(Some("100".parse().unwrap())).unwrap();
+    client.withdraw(Some("80".parse().unwrap())).unwrap();
+    client.dispute(1, EntryKind::Deposit, Some("100".parse().unwrap()), DisputePolicy::AllowNegative).unwrap();
+    assert_eq!(client.available(), "-80".parse::<Money>().unwrap());
+
+    assert_eq!(client.adjust(amount.parse().unwrap()), expected);
+    assert_eq!(client.available(), available.parse::<Money>().unwrap());
+    assert_eq!(client.check_invariants(), Ok(()));
+}
+
+/*
+   The raw setters skip the dispute bookkeeping, the invariant check catches what they leave behind
+ */
+#[test]
+fn test_check_invariants() {
+    let mut client = Client::new(1);
+    client.deposit(Some("50".parse().unwrap())).unwrap();
+    client.dispute(1, EntryKind::Deposit, Some("20".parse().unwrap()), DisputePolicy::Reject).unwrap();
+    assert_eq!(client.check_invariants(), Ok(()));
+
+    client.set_held("5".parse().unwrap()).unwrap();
+    assert_eq!(client.check_invariants(), Err("held 25.0000 != open disputes 20.0000".to_string()));
+    client.set_held("-30".parse().unwrap()).unwrap();
+    assert_eq!(client.check_invariants(), Err("held -5.0000 is negative".to_string()));
+}
+
+/*
+   A tx that is already under dispute can't be disputed a second time, the funds are only held once
+ */
+#[test]
+fn test_dispute_twice_is_rejected() {
+    let mut client = Client::new(1);
+    let amount: Money = "50".parse().unwrap();
+    client.deposit(Some(amount)).unwrap();
+    client.dispute(1, EntryKind::Deposit, Some(amount), DisputePolicy::Reject).unwrap();
+
+    assert_eq!(client.dispute(1, EntryKind::Deposit, Some(amount), DisputePolicy::Reject), Err(EngineError::AlreadyDisputed { tx: 1 }));
+    assert_eq!(client.held(), amount);
+}
+
+/*
+   Direction of funds for every step of a dispute on both kinds of transaction. The client starts
+   with deposits of 50 (tx 1) and 50 (tx 3) and a withdrawal of 40 (tx 2), leaving 60 available.
+   Expected balances are available, held, total.
+
+   I.    Disputed deposit moves funds from available to held
+   II.   Resolved deposit returns held funds to available
+   III.  Charged back deposit removes held funds from total
+   IV.   Disputed withdrawal holds a reversal credit, available is untouched
+   V.    Resolved withdrawal drops the reversal credit
+   VI.   Charged back withdrawal releases the credit to available
+ */
+#[test_case(1, EntryKind::Deposit, "50", "dispute", "10", "50", "60", false)]
+#[test_case(1, EntryKind::Deposit, "50", "resolve", "60", "0", "60", false)]
+#[test_case(1, EntryKind::Deposit, "50", "chargeback", "10", "0", "10", true)]
+#[test_case(2, EntryKind::Withdrawal, "40", "dispute", "60", "40", "100", false)]
+#[test_case(2, EntryKind::Withdrawal, "40", "resolve", "60", "0", "60", false)]
+#[test_case(2, EntryKind::Withdrawal, "40", "chargeback", "100", "0", "100", true)]
+#[allow(clippy::too_many_arguments)]
+fn test_dispute_direction_of_funds(
+    tx: u32,
+    kind: EntryKind,
+    amount: &str,
+    last_step: &str,
+    available: &str,
+    held: &str,
+    total: &str,
+    locked: bool,
+) {
+    logger("client_test.log");
+    let mut client = Client::new(1);
+    client.deposit(Some("50".parse().unwrap())).unwrap();
+    client.withdraw(Some("40".parse().unwrap())).unwrap();
+    client.deposit(Some("50".parse().unwrap())).unwrap();
+
+    client.dispute(tx, kind, Some(amount.parse().unwrap()), DisputePolicy::Reject).unwrap();
+    match last_step {
+        "resolve" => client.resolve(tx).unwrap(),
+        "chargeback" => client.chargeback(tx).unwrap(),
+        _ => {}
+    }
+
+    assert_eq!(client.available(), available.parse::<Money>().unwrap());
+    assert_eq!(client.held(), held.parse::<Money>().unwrap());
+    assert_eq!(client.total(), total.parse::<Money>().unwrap());
+    assert_eq!(client.locked(), locked);
+}
+
+/*
+   A client deposits 100 and withdraws 80 before the deposit is disputed. Expected balances are
+   available, held, total.
+   I.   Reject: the dispute fails and nothing is held
+   II.  AllowNegative: all 100 is held and available goes negative
+ */
+#[test_case(DisputePolicy::Reject, Err(EngineError::InsufficientFunds), "20", "0", "20")]
+#[test_case(DisputePolicy::AllowNegative, Ok(()), "-80", "100", "20")]
+fn test_dispute_policy(policy: DisputePolicy, expected: Result<(), EngineError>, available: &str, held: &str, total: &str) {
+    logger("client_test.log");
+    let mut client = Client::new(1);
+    client.deposit(Some("100".parse().unwrap())).unwrap();
+    client.withdraw(Some("80".parse().unwrap())).unwrap();
+
+    assert_eq!(client.dispute(1, EntryKind::Deposit, Some("100".parse().unwrap()), policy), expected);
+    assert_eq!(client.available(), available.parse::<Money>().unwrap());
+    assert_eq!(client.held(), held.parse::<Money>().unwrap());
+    assert_eq!(client.total(), total.parse::<Money>().unwrap());
+}
@@ -0,0 +1,295 @@
+use crate::money::DECIMAL_PLACES;
+use std::fmt;
+use std::str::FromStr;
+
+/*
+    How the engine treats a deposit or withdrawal whose tx id is already in the history.
+    Reject: the row is not applied and is counted as a duplicate_tx rejection
+    Warn:   the row is applied and a warning is logged
+    Allow:  the row is applied silently, for upstream feeds that are known to be messy
+    For Warn and Allow the history keeps the first transaction with that id, so disputes always
+    reference the original.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    #[default]
+    Reject,
+    Warn,
+    Allow,
+}
+
+impl DuplicatePolicy {
+    pub const VARIANTS: [&'static str; 3] = ["reject", "warn", "allow"];
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "reject" => Ok(DuplicatePolicy::Reject),
+            "warn" => Ok(DuplicatePolicy::Warn),
+            "allow" => Ok(DuplicatePolicy::Allow),
+            other => Err(format!("unknown duplicate policy: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DuplicatePolicy::Reject => "reject",
+            DuplicatePolicy::Warn => "warn",
+            DuplicatePolicy::Allow => "allow",
+        };
+        f.write_str(name)
+    }
+}
+
+/*
+    What happens when a disputed deposit is larger than the client's available balance, for
+    example because the client already withdrew the funds.
+    Reject:        the dispute is rejected with insufficient_funds (the default)
+    AllowNegative: the funds are held anyway and available goes negative, which is how card
+                   networks treat a dispute
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisputePolicy {
+    #[default]
+    Reject,
+    AllowNegative,
+}
+
+impl DisputePolicy {
+    pub const VARIANTS: [&'static str; 2] = ["reject", "allow-negative"];
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "reject" => Ok(DisputePolicy::Reject),
+            "allow-negative" => Ok(DisputePolicy::AllowNegative),
+            other => Err(format!("unknown dispute policy: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for DisputePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DisputePolicy::Reject => "reject",
+            DisputePolicy::AllowNegative => "allow-negative",
+        };
+        f.write_str(name)
+    }
+}
+
+/*
+    What happens to an amount with more decimal places than the AmountRules allow.
+    Reject:        the row is rejected with excess_precision (the default)
+    RoundHalfEven: the amount is rounded to the nearest allowed value and ties go to the even
+                   digit, so rounding many rows doesn't push the ledger up
+    Truncate:      the extra places are dropped, which rounds towards zero
+    A rounded or truncated row is applied with the new amount. It is not a rejection, the change is
+    logged, counted in the run summary and written to the adjustments report so it can be traced
+    back.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrecisionPolicy {
+    #[default]
+    Reject,
+    RoundHalfEven,
+    Truncate,
+}
+
+impl PrecisionPolicy {
+    pub const VARIANTS: [&'static str; 3] = ["reject", "round-half-even", "truncate"];
+}
+
+impl FromStr for PrecisionPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "reject" => Ok(PrecisionPolicy::Reject),
+            "round-half-even" => Ok(PrecisionPolicy::RoundHalfEven),
+            "truncate" => Ok(PrecisionPolicy::Truncate),
+            other => Err(format!("unknown precision policy: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for PrecisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PrecisionPolicy::Reject => "reject",
+            PrecisionPolicy::RoundHalfEven => "round-half-even",
+            PrecisionPolicy::Truncate => "truncate",
+        };
+        f.write_str(name)
+    }
+}
+
+/*
+    How amounts in the input are validated when a row is read. NaN and infinity are never amounts.
+    Trailing zeros don't count as decimal places, 1.50000 is 1.5.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountRules {
+    // Decimal places an amount may have, at most the four the ledger keeps
+    pub max_decimal_places: u32,
+    // Accept exponent notation such as 1.5e2, some exports write large amounts that way
+    pub allow_exponent: bool,
+    pub excess_precision: PrecisionPolicy,
+}
+
+impl Default for AmountRules {
+    fn default() -> Self {
+        Self {
+            max_decimal_places: DECIMAL_PLACES,
+            allow_exponent: false,
+            excess_precision: PrecisionPolicy::default(),
+        }
+    }
+}
+
+/*
+    Order of the rows in the accounts csv. Clients are kept in a hash map, so without sorting the
+    order would change from run to run. Ties are always broken by client id, so the same input
+    gives byte-identical output.
+    ClientId:    ascending client id (the default)
+    Total:       largest total first
+    LockedFirst: locked accounts first, for reviewing chargebacks
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    ClientId,
+    Total,
+    LockedFirst,
+}
+
+impl SortOrder {
+    pub const VARIANTS: [&'static str; 3] = ["client", "total", "locked"];
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "client" => Ok(SortOrder::ClientId),
+            "total" => Ok(SortOrder::Total),
+            "locked" => Ok(SortOrder::LockedFirst),
+            other => Err(format!("unknown sort order: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SortOrder::ClientId => "client",
+            SortOrder::Total => "total",
+            SortOrder::LockedFirst => "locked",
+        };
+        f.write_str(name)
+    }
+}
+
+/*
+    Format of the accounts written at the end of the run.
+    Csv:      one row per client (the default)
+    Json:     one array holding an object per client
+    Jsonl:    one object per client per line, for streaming into other tools
+    Table:    aligned columns for reading in a terminal
+    Columnar: one JSON object holding an array per column, the layout of Parquet and Arrow, for
+              loaders that want a column at a time
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    Jsonl,
+    Table,
+    Columnar,
+}
+
+impl OutputFormat {
+    pub const VARIANTS: [&'static str; 5] = ["csv", "json", "jsonl", "table", "columnar"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "table" => Ok(OutputFormat::Table),
+            "columnar" => Ok(OutputFormat::Columnar),
+            other => Err(format!("unknown output format: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Table => "table",
+            OutputFormat::Columnar => "columnar",
+        };
+        f.write_str(name)
+    }
+}
+
+/*
+    How the accounts are written at the end of the run. Amounts have four decimal places by
+    default, fewer are rounded half away from zero and more are padded with zeros. trim_zeros drops
+    trailing zeros of the fraction, 5.5000 is written as 5.5 and 5.0000 as 5.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub sort: SortOrder,
+    pub precision: usize,
+    pub trim_zeros: bool,
+    // Add each client's open disputes to its row
+    pub include_disputes: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            format: OutputFormat::default(),
+            sort: SortOrder::default(),
+            precision: DECIMAL_PLACES as usize,
+            trim_zeros: false,
+            include_disputes: false,
+        }
+    }
+}
+
+/*
+    Rules the engine applies while processing. The defaults match the behaviour described in the
+    README.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub duplicate_policy: DuplicatePolicy,
+    pub dispute_policy: DisputePolicy,
+    // Number of workers rows are sharded to by client, 0 and 1 both process on the reading thread
+    pub threads: usize,
+    // Whether lock, unlock and adjust rows are accepted, they are rejected as unauthorized otherwise
+    pub allow_admin: bool,
+    // Check the client invariants after every row, always on in debug builds
+    pub check_invariants: bool,
+    pub amounts: AmountRules,
+}
@@ -0,0 +1,143 @@
+use crate::money::MoneyError;
+use serde::{Deserialize, Serialize};
+use crate::transactions::{TransactionKind, TxState};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/*
+    Every way an engine operation can fail. The process_* functions and the Client money methods
+    return these so callers and tests can match on the outcome instead of reading the log. Each
+    variant has a stable reason code that is used when rejections are counted in the run summary.
+    Errors are serializable so the journal can keep the reason a row was rejected with.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EngineError {
+    // A withdrawal or dispute needs more than the available balance
+    InsufficientFunds,
+    // The client's account was locked by a chargeback
+    AccountLocked { client: u16 },
+    // A dispute referenced a tx id that is not in the history
+    UnknownTx { tx: u32 },
+    // A dispute referenced a tx that is already under dispute
+    AlreadyDisputed { tx: u32 },
+    // A resolve or chargeback referenced a tx that is not under dispute
+    NotDisputed { tx: u32 },
+    // The dispute lifecycle does not allow the tx to move between these states
+    IllegalTransition { tx: u32, from: TxState, to: TxState },
+    // A deposit or withdrawal with a missing, zero or negative amount
+    InvalidAmount,
+    // The operation would overflow a balance
+    Overflow,
+    // A dispute, resolve or chargeback referenced a tx that belongs to a different client
+    ClientMismatch { tx: u32, owner: u16, client: u16 },
+    // A deposit or withdrawal reused a tx id that is already in the history
+    DuplicateTx { tx: u32 },
+    // The row could not be read or parsed, row is the line number in the input
+    ParseError { row: u64, reason: String },
+    // An admin row was read while admin rows are not allowed
+    Unauthorized { kind: TransactionKind },
+    // An adjust row whose reason column is missing or empty
+    MissingReason,
+    // The type column is not one the engine knows about
+    UnsupportedType { transaction_type: String },
+    // Reading the input or writing the output failed
+    Io(String),
+    // A client broke one of its invariants after the tx was applied, this stops the run
+    InvariantViolation { tx: u32, client: u16, reason: String },
+    // A saved state could not be loaded, line is the line number in the snapshot
+    InvalidSnapshot { line: u64, reason: String },
+    // A journal was opened for other inputs than the ones it was written for
+    JournalMismatch { reason: String },
+    // An amount with more decimal places than allowed, with the precision policy set to reject
+    ExcessPrecision { amount: String, max_places: u32 },
+}
+
+impl EngineError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            EngineError::InsufficientFunds => "insufficient_funds",
+            EngineError::AccountLocked { .. } => "account_locked",
+            EngineError::UnknownTx { .. } => "unknown_tx",
+            EngineError::AlreadyDisputed { .. } => "already_disputed",
+            EngineError::NotDisputed { .. } => "not_disputed",
+            EngineError::IllegalTransition { .. } => "illegal_transition",
+            EngineError::InvalidAmount => "invalid_amount",
+            EngineError::Overflow => "overflow",
+            EngineError::ClientMismatch { .. } => "client_mismatch",
+            EngineError::DuplicateTx { .. } => "duplicate_tx",
+            EngineError::ParseError { .. } => "parse_error",
+            EngineError::Unauthorized { .. } => "unauthorized",
+            EngineError::MissingReason => "missing_reason",
+            EngineError::UnsupportedType { .. } => "unsupported_type",
+            EngineError::Io(_) => "io_error",
+            EngineError::InvariantViolation { .. } => "invariant_violation",
+            EngineError::InvalidSnapshot { .. } => "invalid_snapshot",
+            EngineError::JournalMismatch { .. } => "journal_mismatch",
+            EngineError::ExcessPrecision { .. } => "excess_precision",
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::InsufficientFunds => write!(f, "insufficient available funds"),
+            EngineError::AccountLocked { client } => write!(f, "account {} is locked", client),
+            EngineError::UnknownTx { tx } => write!(f, "transaction {} not found", tx),
+            EngineError::AlreadyDisputed { tx } => write!(f, "transaction {} is already disputed", tx),
+            EngineError::NotDisputed { tx } => write!(f, "transaction {} is not under dispute", tx),
+            EngineError::IllegalTransition { tx, from, to } => {
+                write!(f, "transaction {} can't move from {:?} to {:?}", tx, from, to)
+            }
+            EngineError::InvalidAmount => write!(f, "amount must be present and greater than zero"),
+            EngineError::Overflow => write!(f, "balance overflow"),
+            EngineError::ClientMismatch { tx, owner, client } => write!(
+                f,
+                "transaction {} belongs to client {}, not client {}",
+                tx, owner, client
+            ),
+            EngineError::DuplicateTx { tx } => write!(f, "transaction id {} has already been used", tx),
+            EngineError::ParseError { row, reason } => write!(f, "row {}: {}", row, reason),
+            EngineError::Unauthorized { kind } => {
+                write!(f, "{} rows need admin rows to be allowed", kind)
+            }
+            EngineError::MissingReason => write!(f, "an adjustment needs a reason"),
+            EngineError::UnsupportedType { transaction_type } => {
+                write!(f, "unsupported transaction type {:?}", transaction_type)
+            }
+            EngineError::Io(reason) => write!(f, "i/o error: {}", reason),
+            EngineError::InvariantViolation { tx, client, reason } => {
+                write!(f, "transaction {} broke an invariant of client {}: {}", tx, client, reason)
+            }
+            EngineError::InvalidSnapshot { line, reason } => write!(f, "snapshot line {}: {}", line, reason),
+            EngineError::JournalMismatch { reason } => write!(f, "the journal can't be resumed, {}", reason),
+            EngineError::ExcessPrecision { amount, max_places } => {
+                write!(f, "amount {} has more than {} decimal places", amount, max_places)
+            }
+        }
+    }
+}
+
+impl Error for EngineError {}
+
+impl From<MoneyError> for EngineError {
+    fn from(error: MoneyError) -> Self {
+        match error {
+            MoneyError::Overflow => EngineError::Overflow,
+            MoneyError::Invalid(_) | MoneyError::TooPrecise { .. } => EngineError::InvalidAmount,
+        }
+    }
+}
+
+impl From<io::Error> for EngineError {
+    fn from(error: io::Error) -> Self {
+        EngineError::Io(error.to_string())
+    }
+}
+
+impl From<csv::Error> for EngineError {
+    fn from(error: csv::Error) -> Self {
+        EngineError::Io(error.to_string())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::errors::EngineError;
+use log::warn;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+Description: Turns the inputs given on the command line into the list of files to process, in order.
+             A directory stands for the .csv files in it, sorted by name. A path whose file name has
+             * or ? in it is a glob: * matches any run of characters and ? matches one. Globs are
+             usually expanded by the shell already, this covers quoted patterns and shells that
+             don't. The matching files are sorted by name, and a glob that matches nothing is an
+             error so a missing shard isn't skipped silently, the same goes for a directory without
+             .csv files. Wildcards in the directory part are not supported. Anything else is taken
+             as it is, including - for stdin.
+             A file reached through more than one input, say a directory and a glob in it, is only
+             processed the first time, a shard applied twice would apply its rows twice.
+Parameters:
+    inputs: &[String]  The inputs as given, in the order they are processed
+*/
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<String>, EngineError> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        if path.is_dir() {
+            let matches = matching_files(path, "*.csv")?;
+            if matches.is_empty() {
+                return Err(EngineError::Io(format!("no .csv input file in {}", input)));
+            }
+            files.extend(matches);
+        } else if file_name.contains(['*', '?']) {
+            let directory = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let matches = match directory.is_dir() {
+                true => matching_files(directory, file_name)?,
+                false => Vec::new(),
+            };
+            if matches.is_empty() {
+                return Err(EngineError::Io(format!("no input file matches {}", input)));
+            }
+            files.extend(matches);
+        } else {
+            files.push(input.clone());
+        }
+    }
+
+    let mut seen = HashSet::new();
+    files.retain(|file| {
+        let first = seen.insert(fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file)));
+        if !first {
+            warn!("Input {} was given more than once, it is only processed the first time", file);
+        }
+        first
+    });
+    Ok(files)
+}
+
+/*
+Description: The files in a directory whose name matches the pattern, sorted by name
+*/
+fn matching_files(directory: &Path, pattern: &str) -> Result<Vec<String>, EngineError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let matched = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| glob_match(pattern, name));
+        if matched && path.is_file() {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/*
+Description: Whether a file name matches a glob pattern where * is any run of characters, including
+             none, and ? is any single character. Names starting with a dot only match a pattern
+             that starts with one, like in the shell.
+Parameters:
+    pattern: &str  The glob, for example day*.csv
+    name: &str  The file name
+*/
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last * was seen and the position in the name it was tried at, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last * take one more character and try again
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
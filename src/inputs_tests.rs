@@ -0,0 +1,69 @@
+use crate::inputs::{expand_inputs, glob_match};
+use std::fs;
+use test_case::test_case;
+
+/*
+   I.    Star matches any run of characters
+   II.   Star matches nothing
+   III.  Question mark matches exactly one character
+   IV.   Question mark needs a character
+   V.    Star has to backtrack past a false start
+   VI.   Different extension
+   VII.  Hidden files need a pattern starting with a dot
+   VIII. No wildcards is an exact match
+ */
+#[test_case("day*.csv", "day12.csv", true)]
+#[test_case("day*.csv", "day.csv", true)]
+#[test_case("day?.csv", "day1.csv", true)]
+#[test_case("day?.csv", "day.csv", false)]
+#[test_case("*a*b.csv", "xaab_ab.csv", true)]
+#[test_case("*.csv", "day1.txt", false)]
+#[test_case("*.csv", ".day1.csv", false)]
+#[test_case("day1.csv", "day1.csv", true)]
+fn test_glob_match(pattern: &str, name: &str, expected: bool) {
+    assert_eq!(glob_match(pattern, name), expected);
+}
+
+/*
+    Inputs keep their order, directories and globs expand to their files sorted by name. A file
+    reached again, through the directory or under another spelling of its path, is left out.
+ */
+#[test]
+fn test_expand_inputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let shards = dir.path().join("shards");
+    fs::create_dir(&shards).unwrap();
+    for name in ["b.csv", "a.csv", "notes.txt"] {
+        fs::write(shards.join(name), "").unwrap();
+    }
+    let first = dir.path().join("day1.csv").to_str().unwrap().to_string();
+    let shard = |name: &str| shards.join(name).to_str().unwrap().to_string();
+
+    fs::write(&first, "").unwrap();
+    let inputs = vec![
+        first.clone(),
+        shards.join("*.csv").to_str().unwrap().to_string(),
+        shards.to_str().unwrap().to_string(),
+        shards.join(".").join("a.csv").to_str().unwrap().to_string(),
+    ];
+    assert_eq!(expand_inputs(&inputs).unwrap(), vec![first, shard("a.csv"), shard("b.csv")]);
+    assert_eq!(expand_inputs(&["-".to_string()]).unwrap(), vec!["-".to_string()]);
+}
+
+#[test]
+fn test_glob_without_matches_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let pattern = dir.path().join("day*.csv").to_str().unwrap().to_string();
+    assert!(expand_inputs(&[pattern]).is_err());
+}
+
+/*
+    A directory without .csv files is an error like a glob without matches, a missing shard is never
+    skipped silently
+ */
+#[test]
+fn test_directory_without_csv_files_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("notes.txt"), "").unwrap();
+    assert!(expand_inputs(&[dir.path().to_str().unwrap().to_string()]).is_err());
+}
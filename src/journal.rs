@@ -0,0 +1,341 @@
+use crate::errors::EngineError;
+use csv::StringRecord;
+use std::fs::{File, OpenOptions};
+use std::fmt;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::str::FromStr;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/*
+    Append-only write-ahead journal of the input rows the engine has taken on. Every row is written
+    to the journal before it is applied, in input order, as one line:
+        <crc32 of the rest of the line, 8 hex digits> <position> <fields as a JSON array> <raw fields>
+    The position is the line number for rows of the first input of a run and <input>:<line> for
+    the inputs after it, so a run over several files can resume inside any of them.
+    The fields are the parsed row in the columns type, client, tx, amount, reason, whatever the
+    column order of the input, so the journal can be replayed before the input's header is read.
+    The raw fields are the row as it was read, they are what a rejected row is reported with.
+    A row that could not be read or parsed is journaled as well, so a resumed run still reports it:
+        <crc32> <position> rejected <raw fields, null when none could be read> <the error as JSON>
+    Replaying the journal from an empty engine rebuilds exactly the state the engine had, because
+    rows are applied deterministically. A run that was killed can therefore be restarted with the
+    same journal and the same inputs in the same order: the journaled rows are replayed and the
+    inputs continue after the last of them.
+    A crash can leave a partly written last line, the checksum catches it and the journal is cut
+    back to the last complete row when it is opened.
+    The first line records the inputs the journal was written for, with the same checksum:
+        <crc32> inputs <the InputFingerprint of every input as a JSON array>
+    A journal is only opened for the inputs it recorded, resuming yesterday's journal with today's
+    file would skip today's first rows and replay yesterday's on top of today's state. A stream
+    such as stdin is only known by its name, so a journal that holds rows of a stream is never
+    resumed, the next stream under the same name could hold anything.
+ */
+pub struct Journal {
+    writer: BufWriter<File>,
+    // The inputs of the run the journal belongs to
+    inputs: Vec<InputFingerprint>,
+    // Number of intact rows found when the journal was opened
+    recovered_rows: usize,
+    // Position of the last intact row, rows up to this one don't have to be read again
+    last_position: InputPosition,
+}
+
+/*
+    Where a row was read: the index of its input in the list of inputs of the run and its line
+    number in that input. Positions order the rows of a run the way they were read.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InputPosition {
+    pub input: usize,
+    pub line: u64,
+}
+
+impl fmt::Display for InputPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.input {
+            0 => write!(f, "{}", self.line),
+            input => write!(f, "{}:{}", input, self.line),
+        }
+    }
+}
+
+impl FromStr for InputPosition {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            Some((input, line)) => Ok(Self {
+                input: input.parse().map_err(|_| ())?,
+                line: line.parse().map_err(|_| ())?,
+            }),
+            None => Ok(Self { input: 0, line: value.parse().map_err(|_| ())? }),
+        }
+    }
+}
+
+/*
+    A row read back from the journal. Parsed holds the row in the usual columns and the row as it
+    was read. Rejected is a row that could not be read or parsed, with the fields that were read of
+    it and the error it was rejected with.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournaledRow {
+    Parsed { record: StringRecord, raw: StringRecord },
+    Rejected { raw: Option<StringRecord>, reason: EngineError },
+}
+
+/*
+    What a journal records about one input of its run: the path, the size and the header line of
+    the file. Size and header are None for a stream such as stdin, which is only known by its name.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputFingerprint {
+    pub path: String,
+    pub size: Option<u64>,
+    pub header: Option<String>,
+}
+
+impl InputFingerprint {
+    pub fn of_file(path: &str) -> Result<Self, EngineError> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut header = String::new();
+        BufReader::new(file).read_line(&mut header)?;
+        Ok(Self {
+            path: path.to_string(),
+            size: Some(size),
+            header: Some(header.trim_end().to_string()),
+        })
+    }
+
+    pub fn of_stream(name: &str) -> Self {
+        Self { path: name.to_string(), size: None, header: None }
+    }
+}
+
+impl Journal {
+    /*
+    Description: Opens the journal at path for a run over the given inputs, creating it when it
+                 doesn't exist. An existing journal has to have been written for the same inputs,
+                 otherwise it is refused with journal_mismatch and left as it is. It is then checked
+                 line by line and anything after the first damaged line is cut off. A journal with
+                 rows from a stream is refused as well, the stream can't be checked.
+    Parameters:
+        path: &str  Location of the journal
+        inputs: &[InputFingerprint]  The inputs of the run, in the order they are processed
+    */
+    pub fn open(path: &str, inputs: &[InputFingerprint]) -> Result<Self, EngineError> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut intact_len = 0;
+        let mut recovered_rows = 0;
+        let mut last_position = InputPosition::default();
+        {
+            let mut reader = BufReader::new(&mut file);
+            let mut line = String::new();
+            let read = reader.read_line(&mut line)?;
+            match decode_inputs(&line) {
+                Some(recorded) if recorded == inputs => intact_len = read as u64,
+                Some(recorded) => {
+                    return Err(EngineError::JournalMismatch {
+                        reason: format!("it was written for {} and not for {}", input_names(&recorded), input_names(inputs)),
+                    });
+                }
+                // Empty, or cut off while the first line was written, before any row was journaled
+                None if !line.contains('\n') => {}
+                None => {
+                    return Err(EngineError::JournalMismatch { reason: "it doesn't record its inputs".to_string() });
+                }
+            }
+            loop {
+                line.clear();
+                let read = reader.read_line(&mut line)?;
+                if read == 0 {
+                    break;
+                }
+                match decode_line(&line) {
+                    Some((position, _)) => {
+                        intact_len += read as u64;
+                        recovered_rows += 1;
+                        last_position = position;
+                    }
+                    None => {
+                        warn!("Journal {} is damaged after {} rows, cutting off the rest", path, recovered_rows);
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(stream) = inputs.iter().find(|input| input.size.is_none()).filter(|_| recovered_rows > 0) {
+            return Err(EngineError::JournalMismatch {
+                reason: format!("it holds rows read from {} and a stream can't be checked to be the same again", stream.path),
+            });
+        }
+        file.set_len(intact_len)?;
+        file.seek(SeekFrom::Start(intact_len))?;
+        let mut journal = Self {
+            writer: BufWriter::new(file),
+            inputs: inputs.to_vec(),
+            recovered_rows,
+            last_position,
+        };
+        if intact_len == 0 {
+            let inputs = serde_json::to_string(inputs).map_err(|err| EngineError::Io(err.to_string()))?;
+            journal.write_line(&format!("inputs {}", inputs))?;
+            journal.flush()?;
+        }
+        Ok(journal)
+    }
+
+    pub fn inputs(&self) -> &[InputFingerprint] {
+        &self.inputs
+    }
+
+    pub fn recovered_rows(&self) -> usize {
+        self.recovered_rows
+    }
+
+    pub fn last_position(&self) -> InputPosition {
+        self.last_position
+    }
+
+    /*
+    Description: Calls apply with every intact row in the journal, in the order they were written
+    Parameters:
+        apply: FnMut(InputPosition, JournaledRow)  Receives where the row was read and the row
+    */
+    pub fn replay<F>(&mut self, mut apply: F) -> Result<(), EngineError>
+    where
+        F: FnMut(InputPosition, JournaledRow) -> Result<(), EngineError>,
+    {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&mut *file);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        for _ in 0..self.recovered_rows {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let (position, row) = decode_line(&line)
+                .ok_or_else(|| EngineError::Io("journal changed while replaying".to_string()))?;
+            apply(position, row)?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    /*
+    Description: Writes a row to the journal
+    Parameters:
+        position: InputPosition  Where the row was read
+        record: &StringRecord  The parsed row in the usual columns
+        raw: &StringRecord  The row as it was read
+    */
+    pub fn append(&mut self, position: InputPosition, record: &StringRecord, raw: &StringRecord) -> Result<(), EngineError> {
+        self.write_line(&format!("{} {} {}", position, to_json(&fields(record))?, to_json(&fields(raw))?))?;
+        self.last_position = position;
+        Ok(())
+    }
+
+    /*
+    Description: Writes a row that could not be read or parsed to the journal
+    Parameters:
+        position: InputPosition  Where the row was read
+        raw: Option<&StringRecord>  The row as it was read, None when it could not be read at all
+        reason: &EngineError  Why the row was rejected
+    */
+    pub fn append_rejected(&mut self, position: InputPosition, raw: Option<&StringRecord>, reason: &EngineError) -> Result<(), EngineError> {
+        self.write_line(&format!("{} rejected {} {}", position, to_json(&raw.map(fields))?, to_json(reason)?))?;
+        self.last_position = position;
+        Ok(())
+    }
+
+    fn write_line(&mut self, body: &str) -> Result<(), EngineError> {
+        writeln!(self.writer, "{:08x} {}", crc32(body.as_bytes()), body)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), EngineError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
+/*
+Description: The body of a journal line, None when it is incomplete or the checksum doesn't match
+*/
+fn checked_body(line: &str) -> Option<&str> {
+    let line = line.strip_suffix('\n')?;
+    let (checksum, body) = line.split_once(' ')?;
+    (u32::from_str_radix(checksum, 16).ok()? == crc32(body.as_bytes())).then_some(body)
+}
+
+/*
+Description: Reads the inputs line back, None when the line is not an intact inputs line
+*/
+fn decode_inputs(line: &str) -> Option<Vec<InputFingerprint>> {
+    serde_json::from_str(checked_body(line)?.strip_prefix("inputs ")?).ok()
+}
+
+fn input_names(inputs: &[InputFingerprint]) -> String {
+    inputs.iter().map(|input| input.path.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+fn fields(record: &StringRecord) -> Vec<&str> {
+    record.iter().collect()
+}
+
+fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String, EngineError> {
+    serde_json::to_string(value).map_err(|err| EngineError::Io(err.to_string()))
+}
+
+/*
+Description: Reads a journaled row back, None when it is incomplete or the checksum doesn't match
+*/
+fn decode_line(line: &str) -> Option<(InputPosition, JournaledRow)> {
+    let (position, body) = checked_body(line)?.split_once(' ')?;
+    let rejected = body.strip_prefix("rejected ");
+    let mut values = serde_json::Deserializer::from_str(rejected.unwrap_or(body)).into_iter::<serde_json::Value>();
+    let first = values.next()?.ok()?;
+    let second = values.next()?.ok()?;
+    if values.next().is_some() {
+        return None;
+    }
+    let record = |value| serde_json::from_value::<Vec<String>>(value).ok().map(StringRecord::from);
+    let row = if rejected.is_some() {
+        JournaledRow::Rejected {
+            raw: serde_json::from_value::<Option<Vec<String>>>(first).ok()?.map(StringRecord::from),
+            reason: serde_json::from_value(second).ok()?,
+        }
+    } else {
+        JournaledRow::Parsed { record: record(first)?, raw: record(second)? }
+    };
+    Some((position.parse().ok()?, row))
+}
+
+// Lookup table for the reflected CRC-32 polynomial used by zip, png and ethernet
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
@@ -0,0 +1,339 @@
+use crate::adjustments::AdjustmentsWriter;
+use crate::config::{AmountRules, EngineConfig, PrecisionPolicy};
+use crate::errors::EngineError;
+use crate::rejects::RejectsWriter;
+use crate::journal::*;
+use crate::transactions::*;
+use crate::transactions_tests::{balances, generate_rows};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use test_case::test_case;
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+fn journaled_engine(path: &str, threads: usize, input: &str) -> (PaymentsEngine, RunSummary) {
+    journaled_engine_for(path, threads, &fingerprints(&[input]))
+}
+
+fn journaled_engine_for(path: &str, threads: usize, inputs: &[InputFingerprint]) -> (PaymentsEngine, RunSummary) {
+    let mut engine = PaymentsEngine::with_config(EngineConfig { threads, ..EngineConfig::default() });
+    let recovered = engine.recover_from_journal(Journal::open(path, inputs).unwrap()).unwrap();
+    (engine, recovered)
+}
+
+fn fingerprints(files: &[&str]) -> Vec<InputFingerprint> {
+    files.iter().map(|file| InputFingerprint::of_file(file).unwrap()).collect()
+}
+
+fn input_file(dir: &tempfile::TempDir, content: &[u8]) -> String {
+    let path = dir.path().join("input.csv");
+    fs::write(&path, content).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/*
+Description: Simulates a run that was killed after the first rows of the journal were written
+*/
+fn keep_journal_rows(path: &str, rows: usize) {
+    let journal = fs::read_to_string(path).unwrap();
+    let crashed = journal.lines().take(rows + 1).map(|line| format!("{}\n", line)).collect::<String>();
+    fs::write(path, crashed).unwrap();
+}
+
+/*
+    The first run is killed after part of the input, in the middle of writing a journal line. The
+    rerun with the same journal and the full input ends with the same balances as a single clean
+    run and every row is counted exactly once.
+ */
+#[test_case(1)]
+#[test_case(4)]
+fn test_recover_after_crash(threads: usize) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let input = generate_rows(3_000, 25, 5);
+    let file = input_file(&dir, input.as_bytes());
+
+    let (first, _) = journaled_engine(path, threads, &file);
+    first.process_files(&[&file]).unwrap();
+    drop(first);
+    keep_journal_rows(path, 1_700);
+    OpenOptions::new().append(true).open(path).unwrap().write_all(b"0badf00d 1702 [\"dep").unwrap();
+
+    let (rerun, recovered) = journaled_engine(path, threads, &file);
+    assert_eq!(recovered.rows_read, 1_700);
+    let summary = rerun.process_files(&[&file]).unwrap();
+    assert_eq!(recovered.rows_read + summary.rows_read, 3_000);
+
+    let clean = PaymentsEngine::new();
+    let clean_summary = clean.process_transactions_from_reader(input.as_bytes()).unwrap();
+    assert_eq!(recovered.rows_rejected + summary.rows_rejected, clean_summary.rows_rejected);
+    assert_eq!(balances(&rerun), balances(&clean));
+}
+
+/*
+    Rerunning a finished input with its journal applies nothing twice
+ */
+#[test]
+fn test_rerun_of_finished_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let file = input_file(&dir, b"type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,2.5\n");
+
+    let (first, _) = journaled_engine(path, 1, &file);
+    first.process_files(&[&file]).unwrap();
+    let (rerun, _) = journaled_engine(path, 1, &file);
+    let summary = rerun.process_files(&[&file]).unwrap();
+
+    assert_eq!(summary.rows_read, 0);
+    assert_eq!(balances(&rerun), balances(&first));
+}
+
+/*
+    A journal line whose checksum doesn't match is cut off together with everything after it
+ */
+#[test]
+fn test_damaged_journal_is_cut() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let file = input_file(&dir, b"type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,2,2\ndeposit,1,3,3\n");
+    let (engine, _) = journaled_engine(path, 1, &file);
+    engine.process_files(&[&file]).unwrap();
+    drop(engine);
+
+    let journal = fs::read_to_string(path).unwrap();
+    let lines: Vec<&str> = journal.lines().collect();
+    assert_eq!(lines.len(), 4);
+    fs::write(path, journal.replace("deposit\",\"1\",\"2\",\"2", "deposit\",\"1\",\"2\",\"9")).unwrap();
+
+    let journal = Journal::open(path, &fingerprints(&[&file])).unwrap();
+    assert_eq!(journal.recovered_rows(), 1);
+    assert_eq!(journal.last_position(), InputPosition { input: 0, line: 2 });
+    drop(journal);
+    assert_eq!(fs::read_to_string(path).unwrap(), format!("{}\n{}\n", lines[0], lines[1]));
+}
+
+/*
+    Rows are journaled in the usual column order, so an input with its own column order is replayed
+    correctly. Line 3 can't be parsed and is journaled as rejected, it can never change a balance.
+ */
+#[test]
+fn test_recover_reordered_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let file = input_file(&dir, b"amount,tx,client,type\n10.0,1,1,deposit\n1.0,x,1,deposit\n,1,1,dispute\n2.5,3,2,deposit\n");
+
+    let (first, _) = journaled_engine(path, 1, &file);
+    first.process_files(&[&file]).unwrap();
+    let journal = fs::read_to_string(path).unwrap();
+    assert_eq!(journal.lines().count(), 5);
+    assert!(journal.lines().nth(1).unwrap().ends_with(" 2 [\"deposit\",\"1\",\"1\",\"10.0000\",\"\"] [\"10.0\",\"1\",\"1\",\"deposit\"]"));
+    assert!(journal.lines().nth(2).unwrap().contains(" 3 rejected [\"1.0\",\"x\",\"1\",\"deposit\"] {\"ParseError\""));
+
+    let (rerun, recovered) = journaled_engine(path, 1, &file);
+    assert_eq!((recovered.rows_read, recovered.rows_rejected), (4, 1));
+    assert_eq!(balances(&rerun), balances(&first));
+}
+
+/*
+    A run over two files is killed inside the second one, before its last row was journaled. The
+    rerun over the same files skips the whole first file and the journaled part of the second, and
+    ends with the balances of a clean run.
+ */
+#[test]
+fn test_recover_across_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let day1 = dir.path().join("day1.csv");
+    let day2 = dir.path().join("day2.csv");
+    let files = [day1.to_str().unwrap(), day2.to_str().unwrap()];
+    fs::write(&day1, "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,1,2,2.0\n").unwrap();
+    fs::write(&day2, "type,client,tx,amount\ndeposit,2,3,5.0\ndispute,1,1,\nwithdrawal,2,4,1.0\n").unwrap();
+
+    let (first, _) = journaled_engine_for(path, 1, &fingerprints(&files));
+    first.process_files(&files).unwrap();
+    drop(first);
+    keep_journal_rows(path, 4);
+    let journal = Journal::open(path, &fingerprints(&files)).unwrap();
+    assert_eq!(journal.last_position(), InputPosition { input: 1, line: 3 });
+    drop(journal);
+
+    let (rerun, recovered) = journaled_engine_for(path, 1, &fingerprints(&files));
+    let summary = rerun.process_files(&files).unwrap();
+    assert_eq!((recovered.rows_read, summary.rows_read), (4, 1));
+
+    let clean = PaymentsEngine::new();
+    clean.process_files(&files).unwrap();
+    assert_eq!(balances(&rerun), balances(&clean));
+}
+
+/*
+    A replayed row that is rejected again is reported with its file and the row as it was read,
+    not the journal's copy of it in the usual columns
+ */
+#[test]
+fn test_replayed_rejects_name_their_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let day1 = dir.path().join("day1.csv");
+    let day2 = dir.path().join("day2.csv");
+    let files = [day1.to_str().unwrap(), day2.to_str().unwrap()];
+    fs::write(&day1, "type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+    fs::write(&day2, "amount,tx,client,type\n50.0,2,1,withdrawal\n").unwrap();
+    let (first, _) = journaled_engine_for(path, 1, &fingerprints(&files));
+    first.process_files(&files).unwrap();
+    drop(first);
+
+    let rejects_path = dir.path().join("rejects.csv");
+    let mut rerun = PaymentsEngine::new();
+    rerun.set_rejects_writer(RejectsWriter::from_path(rejects_path.to_str().unwrap()).unwrap());
+    let recovered = rerun.recover_from_journal(Journal::open(path, &fingerprints(&files)).unwrap()).unwrap();
+    rerun.process_files(&files).unwrap();
+
+    assert_eq!(recovered.rows_rejected, 1);
+    assert_eq!(
+        fs::read_to_string(rejects_path).unwrap(),
+        format!(
+            "file,line,record,tx,client,reason,detail\n{},2,\"50.0,2,1,withdrawal\",2,1,insufficient_funds,insufficient available funds\n",
+            files[1]
+        )
+    );
+}
+
+/*
+    Rows that could not be read or parsed are journaled too. A rerun with the same journal writes
+    the same rejects report and counts the same rejections as the run that wrote the journal.
+ */
+#[test]
+fn test_rerun_reports_unparseable_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let file = input_file(&dir, b"amount,client,tx,type\nabc,1,1,deposit\n5.0,1,2,withdrawal\n1.0,1,\xff,deposit\n");
+    let run = |rejects: &str| {
+        let mut engine = PaymentsEngine::new();
+        engine.set_rejects_writer(RejectsWriter::from_path(dir.path().join(rejects).to_str().unwrap()).unwrap());
+        let recovered = engine.recover_from_journal(Journal::open(path, &fingerprints(&[&file])).unwrap()).unwrap();
+        let summary = engine.process_files(&[&file]).unwrap();
+        drop(engine);
+        (recovered, summary, fs::read_to_string(dir.path().join(rejects)).unwrap())
+    };
+
+    let (_, first, first_rejects) = run("first.csv");
+    assert_eq!(first.rejections.get("parse_error"), Some(&2));
+    let (recovered, summary, rerun_rejects) = run("rerun.csv");
+    assert_eq!(summary.rows_read, 0);
+    assert_eq!(recovered.rejections, first.rejections);
+    assert_eq!(rerun_rejects, first_rejects);
+    assert_eq!(rerun_rejects.lines().count(), 4);
+}
+
+/*
+    A journal is refused for other inputs than the ones it was written for and left as it is:
+    i.    The next day's file under another name
+    ii.   The same name with other content
+    iii.  A journal from before the inputs were recorded
+ */
+#[test_case("day2.csv", "type,client,tx,amount\ndeposit,1,1,10.0\n" ; "other file")]
+#[test_case("day1.csv", "type,client,tx,amount\ndeposit,1,5,99.0\ndeposit,1,6,1.0\n" ; "other content")]
+#[test_case("legacy", "" ; "legacy journal")]
+fn test_journal_for_other_inputs_is_refused(file: &str, content: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let day1 = dir.path().join("day1.csv");
+    fs::write(&day1, "type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+    let (first, _) = journaled_engine_for(path, 1, &fingerprints(&[day1.to_str().unwrap()]));
+    first.process_files(&[day1.to_str().unwrap()]).unwrap();
+    drop(first);
+
+    let next = dir.path().join(file);
+    let inputs = if file == "legacy" {
+        let journal = fs::read_to_string(path).unwrap();
+        fs::write(path, journal.lines().skip(1).map(|line| format!("{}\n", line)).collect::<String>()).unwrap();
+        fingerprints(&[day1.to_str().unwrap()])
+    } else {
+        fs::write(&next, content).unwrap();
+        fingerprints(&[next.to_str().unwrap()])
+    };
+    let journal = fs::read_to_string(path).unwrap();
+    assert!(matches!(Journal::open(path, &inputs), Err(EngineError::JournalMismatch { .. })));
+    assert_eq!(fs::read_to_string(path).unwrap(), journal);
+}
+
+/*
+    A row the csv reader can't read after the journaled part of the input is still read, counted
+    and rejected on its own line instead of being taken for a recovered row
+ */
+#[test]
+fn test_unreadable_row_after_resume() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let file = input_file(&dir, b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\ndeposit,1,3,1.0\ndeposit,1,4,1.0\ndeposit,1,\xff,1.0\n");
+
+    let (first, _) = journaled_engine(path, 1, &file);
+    first.process_files(&[&file]).unwrap();
+    drop(first);
+    keep_journal_rows(path, 3);
+
+    let (rerun, _) = journaled_engine(path, 1, &file);
+    let summary = rerun.process_files(&[&file]).unwrap();
+    assert_eq!((summary.rows_read, summary.rows_rejected), (2, 1));
+    assert_eq!(summary.rejections.get("parse_error"), Some(&1));
+}
+
+/*
+    A stream is only known by its name. A journal that holds rows of stdin is refused and left as
+    it is, the next stream could be other rows and they would be skipped.
+ */
+#[test]
+fn test_stream_journal_is_not_resumed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let stdin = [InputFingerprint::of_stream("-")];
+    let (first, _) = journaled_engine_for(path, 1, &stdin);
+    first.process_transactions_from_reader("type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,10\n".as_bytes()).unwrap();
+    drop(first);
+
+    let journal = fs::read_to_string(path).unwrap();
+    assert!(matches!(Journal::open(path, &stdin), Err(EngineError::JournalMismatch { .. })));
+    assert_eq!(fs::read_to_string(path).unwrap(), journal);
+}
+
+/*
+    The journal keeps the adjusted amount. A rerun finds the adjustment again in the row as it was
+    read, so the resumed run's adjustments report still lists it.
+ */
+#[test]
+fn test_rerun_reports_adjusted_amounts() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("journal.log");
+    let path = path.to_str().unwrap();
+    let file = input_file(&dir, b"amount,client,tx,type\n1.00015,1,1,deposit\n");
+    let run = |adjustments: &str| {
+        let mut engine = PaymentsEngine::with_config(EngineConfig {
+            amounts: AmountRules { excess_precision: PrecisionPolicy::Truncate, ..AmountRules::default() },
+            ..EngineConfig::default()
+        });
+        engine.set_adjustments_writer(AdjustmentsWriter::from_path(dir.path().join(adjustments).to_str().unwrap()).unwrap());
+        let recovered = engine.recover_from_journal(Journal::open(path, &fingerprints(&[&file])).unwrap()).unwrap();
+        let summary = engine.process_files(&[&file]).unwrap();
+        drop(engine);
+        (recovered.amounts_adjusted + summary.amounts_adjusted, fs::read_to_string(dir.path().join(adjustments)).unwrap())
+    };
+
+    let first = run("first.csv");
+    assert_eq!(first.0, 1);
+    assert_eq!(run("rerun.csv"), first);
+}
@@ -0,0 +1,61 @@
+/*
+    The payments engine as a library. The accountant binary is a thin command line wrapper around
+    it; services can embed the engine and feed it transactions directly:
+        let engine = Engine::with_config(EngineConfig::default());
+        engine.apply(&Transaction::builder(TransactionKind::Deposit, 1, 1).amount(amount).build())?;
+        let client = engine.client(1);
+    The modules are private, everything a caller can use is re-exported here: the engine and its
+    configuration, transaction stores, snapshots, the journal, the rejects and adjustments reports and the
+    accounts output. Balances and the transaction history can only be read from outside, they change
+    through the engine.
+ */
+mod adjustments;
+mod client;
+mod config;
+mod errors;
+mod inputs;
+mod journal;
+mod money;
+mod output;
+mod rejects;
+mod snapshot;
+mod store;
+mod transactions;
+#[cfg(test)]
+mod client_tests;
+#[cfg(test)]
+mod inputs_tests;
+#[cfg(test)]
+mod journal_tests;
+#[cfg(test)]
+mod model_tests;
+#[cfg(test)]
+mod money_tests;
+#[cfg(test)]
+mod output_tests;
+#[cfg(test)]
+mod snapshot_tests;
+#[cfg(test)]
+mod store_tests;
+#[cfg(test)]
+mod transactions_tests;
+
+pub use adjustments::{Adjustment, AdjustmentsWriter};
+pub use client::{Client, DisputedTransaction};
+pub use config::{
+    AmountRules, DisputePolicy, DuplicatePolicy, EngineConfig, OutputFormat, OutputOptions, PrecisionPolicy, SortOrder,
+};
+pub use errors::EngineError;
+pub use inputs::expand_inputs;
+pub use journal::{InputFingerprint, InputPosition, Journal, JournaledRow};
+pub use money::{Money, MoneyError, ParsedAmount};
+pub use output::write_accounts;
+pub use rejects::{Rejection, RejectsWriter};
+pub use snapshot::{load_state, load_state_from_path, save_state, save_state_to_path};
+pub use store::{DiskStore, InMemoryStore, TransactionStore};
+pub use transactions::{
+    EntryKind, LedgerEntry, PaymentsEngine, RunSummary, Transaction, TransactionBuilder, TransactionKind, TxState,
+};
+
+// The engine under the name the library uses, PaymentsEngine is kept for existing code
+pub type Engine = transactions::PaymentsEngine;
@@ -1,35 +1,117 @@
-mod transactions;
-pub mod utils;
-mod client;
-mod client_tests;
+mod utils;
 
-use transactions::process_transactions;
-use utils::{parse_cli_arguments, write_clients_to_csv, setup_logger};
-use std::process;
+use accountant::{
+    expand_inputs, load_state_from_path, save_state_to_path, write_accounts, AdjustmentsWriter, DiskStore, Engine,
+    InputFingerprint, Journal, RejectsWriter,
+};
+use utils::{parse_cli_arguments, setup_logger, STDIN_INPUT};
+use std::{io, process};
 use log::{info, error};
 
 fn main() {
+    process::exit(run());
+}
+
+/*
+Description: Runs the program and returns its exit code. Everything the run creates lives in here
+             and is dropped before main exits, process::exit skips destructors and the history
+             file is only removed when its store is dropped.
+*/
+fn run() -> i32 {
     let log_file = "transactions.log";
     if let Err(err) = setup_logger(log_file) {
         error!("Failed to initialize logger: {}", err);
-        std::process::exit(1);
+        return 1;
     }
 
     info!("Transactions initialized!");
-    let input_file = parse_cli_arguments();
-    match process_transactions(&input_file) {
-        Ok(client_map) => {
-            if let Err(err) = write_clients_to_csv(&client_map) {
-                error!("Error writing to CSV: {}", err);
-                process::exit(1);
+    let arguments = parse_cli_arguments();
+    let inputs = match expand_inputs(&arguments.inputs) {
+        Ok(inputs) => inputs,
+        Err(err) => {
+            error!("Failed to find the input files: {}", err);
+            return 1;
+        }
+    };
+    if inputs.len() > 1 && inputs.iter().any(|input| input == STDIN_INPUT) {
+        error!("stdin can't be read together with input files");
+        return 1;
+    }
+    if arguments.journal.is_some() && inputs == [STDIN_INPUT] {
+        error!("A journal needs input files, stdin can't be checked to be the same stream when the run is resumed");
+        return 1;
+    }
+    let mut engine = Engine::with_config(arguments.config);
+    if let Some(rejects_file) = &arguments.rejects {
+        match RejectsWriter::from_path(rejects_file) {
+            Ok(writer) => engine.set_rejects_writer(writer),
+            Err(err) => {
+                error!("Failed to create rejects file {}: {}", rejects_file, err);
+                return 1;
+            }
+        }
+    }
+    if let Some(adjustments_file) = &arguments.adjustments {
+        match AdjustmentsWriter::from_path(adjustments_file) {
+            Ok(writer) => engine.set_adjustments_writer(writer),
+            Err(err) => {
+                error!("Failed to create adjustments file {}: {}", adjustments_file, err);
+                return 1;
+            }
+        }
+    }
+    if let Some(history_file) = &arguments.history_file {
+        match DiskStore::create(history_file) {
+            Ok(store) => engine.set_transaction_store(Box::new(store)),
+            Err(err) => {
+                error!("Failed to create history file {}: {}", history_file, err);
+                return 1;
             }
         }
+    }
+    if let Some(state_file) = &arguments.load_state {
+        if let Err(err) = load_state_from_path(&engine, state_file) {
+            error!("Failed to load state from {}: {}", state_file, err);
+            return 1;
+        }
+    }
+    if let Some(journal_file) = &arguments.journal {
+        let fingerprints: Result<Vec<InputFingerprint>, _> =
+            inputs.iter().map(|input| InputFingerprint::of_file(input)).collect();
+        let recovered = fingerprints.and_then(|fingerprints| Journal::open(journal_file, &fingerprints)).and_then(|journal| {
+            info!("Journal {} holds {} rows", journal_file, journal.recovered_rows());
+            engine.recover_from_journal(journal)
+        });
+        if let Err(err) = recovered {
+            error!("Failed to recover from journal {}: {}", journal_file, err);
+            return 1;
+        }
+    }
+    let result = if inputs == [STDIN_INPUT] {
+        info!("Reading transactions from stdin");
+        engine.process_transactions_from_reader(io::stdin().lock())
+    } else {
+        info!("Reading transactions from {} files", inputs.len());
+        engine.process_files(&inputs)
+    };
+    match result {
+        Ok(summary) => {
+            info!("Processed transactions: {}", summary);
+            if let Err(err) = write_accounts(&engine, &arguments.output, io::stdout()) {
+                error!("Error writing the accounts: {}", err);
+                return 1;
+            }
+            if let Some(state_file) = &arguments.save_state {
+                if let Err(err) = save_state_to_path(&engine, state_file) {
+                    error!("Failed to save state to {}: {}", state_file, err);
+                    return 1;
+                }
+            }
+            0
+        }
         Err(err) => {
             error!("Error processing transactions: {}", err);
-            process::exit(1);
+            1
         }
     }
 }
-
-
-
@@ -1,3 +0,0 @@
-pub mod transactions;
-pub mod utils;
-pub mod client;
\ No newline at end of file
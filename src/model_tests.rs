@@ -0,0 +1,352 @@
+use crate::config::EngineConfig;
+use crate::money::Money;
+use crate::transactions::PaymentsEngine;
+use std::collections::HashMap;
+use test_case::test_case;
+
+/*
+    Property based and differential tests for the whole engine. Random but well formed transaction
+    streams are generated from a seed, run through the engine and through a small reference model
+    that applies the Rules of Account in the plainest way possible. The two have to agree on every
+    client, and the model's own bookkeeping has to show that money is conserved and that only
+    chargebacks lock accounts. A failing stream is shrunk to the smallest csv that still fails.
+    The number of streams per test can be raised with ENGINE_PROPERTY_CASES.
+ */
+
+// splitmix64, small and good enough to drive the generator
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Deposit { client: u16, tx: u32, amount: i64 },
+    Withdrawal { client: u16, tx: u32, amount: i64 },
+    Dispute { client: u16, tx: u32 },
+    Resolve { client: u16, tx: u32 },
+    Chargeback { client: u16, tx: u32 },
+}
+
+impl Op {
+    fn to_row(self) -> String {
+        match self {
+            Op::Deposit { client, tx, amount } => format!("deposit,{},{},{}", client, tx, Money::from_raw(amount)),
+            Op::Withdrawal { client, tx, amount } => format!("withdrawal,{},{},{}", client, tx, Money::from_raw(amount)),
+            Op::Dispute { client, tx } => format!("dispute,{},{},", client, tx),
+            Op::Resolve { client, tx } => format!("resolve,{},{},", client, tx),
+            Op::Chargeback { client, tx } => format!("chargeback,{},{},", client, tx),
+        }
+    }
+}
+
+fn to_csv(ops: &[Op]) -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for op in ops {
+        csv.push_str(&op.to_row());
+        csv.push('\n');
+    }
+    csv
+}
+
+/*
+    Builds a stream of length ops over clients clients. Deposits and withdrawals use fresh tx ids,
+    disputes mostly reference an earlier tx of the same client and resolves and chargebacks mostly
+    an earlier dispute, so the stream reaches deep into the dispute lifecycle. A few references
+    go to another client's tx or are repeated to exercise the rejections.
+ */
+fn generate(rng: &mut Rng, ops: usize, clients: u16) -> Vec<Op> {
+    let mut stream = Vec::with_capacity(ops);
+    let mut money_moved: Vec<(u16, u32)> = Vec::new();
+    let mut disputed: Vec<(u16, u32)> = Vec::new();
+    let mut next_tx = 1;
+    for _ in 0..ops {
+        let client = rng.below(clients as u64) as u16 + 1;
+        let amount = rng.below(1_000_000) as i64 + 1;
+        let own = |history: &Vec<(u16, u32)>, rng: &mut Rng| {
+            let candidates: Vec<u32> = history.iter().filter(|(owner, _)| *owner == client).map(|(_, tx)| *tx).collect();
+            if candidates.is_empty() || rng.below(10) == 0 {
+                history.get(rng.below(history.len().max(1) as u64) as usize).map_or(next_tx, |(_, tx)| *tx)
+            } else {
+                candidates[rng.below(candidates.len() as u64) as usize]
+            }
+        };
+        let op = match rng.below(20) {
+            0..=7 => Op::Deposit { client, tx: next_tx, amount },
+            8..=11 => Op::Withdrawal { client, tx: next_tx, amount: amount / 2 },
+            12..=14 => Op::Dispute { client, tx: own(&money_moved, rng) },
+            15..=17 => Op::Resolve { client, tx: own(&disputed, rng) },
+            _ => Op::Chargeback { client, tx: own(&disputed, rng) },
+        };
+        match op {
+            Op::Deposit { tx, .. } | Op::Withdrawal { tx, .. } => {
+                money_moved.push((client, tx));
+                next_tx += 1;
+            }
+            Op::Dispute { tx, .. } => disputed.push((client, tx)),
+            _ => {}
+        }
+        stream.push(op);
+    }
+    stream
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Failed,
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Movement {
+    client: u16,
+    deposit: bool,
+    amount: i64,
+    state: State,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Account {
+    available: i64,
+    held: i64,
+    total: i64,
+    locked: bool,
+}
+
+/*
+    The reference model. Amounts are plain ten-thousandths and every rule is checked in the order
+    the README lists them, one row at a time.
+ */
+#[derive(Default)]
+struct Model {
+    accounts: HashMap<u16, Account>,
+    movements: HashMap<u32, Movement>,
+}
+
+impl Model {
+    fn apply(&mut self, op: Op) {
+        let (client, tx) = match op {
+            Op::Deposit { client, tx, .. }
+            | Op::Withdrawal { client, tx, .. }
+            | Op::Dispute { client, tx }
+            | Op::Resolve { client, tx }
+            | Op::Chargeback { client, tx } => (client, tx),
+        };
+        let account = self.accounts.entry(client).or_default();
+        match op {
+            Op::Deposit { amount, .. } | Op::Withdrawal { amount, .. } => {
+                let deposit = matches!(op, Op::Deposit { .. });
+                if self.movements.contains_key(&tx) {
+                    return;
+                }
+                let applied = !account.locked && (deposit || account.available >= amount);
+                if applied {
+                    let signed = if deposit { amount } else { -amount };
+                    account.available += signed;
+                    account.total += signed;
+                }
+                let state = if applied { State::Processed } else { State::Failed };
+                self.movements.insert(tx, Movement { client, deposit, amount, state });
+            }
+            _ => {
+                let Some(movement) = self.movements.get_mut(&tx) else { return };
+                if movement.client != client || account.locked {
+                    return;
+                }
+                let amount = movement.amount;
+                match (op, movement.state, movement.deposit) {
+                    (Op::Dispute { .. }, State::Processed, true) if account.available >= amount => {
+                        account.available -= amount;
+                        account.held += amount;
+                        movement.state = State::Disputed;
+                    }
+                    (Op::Dispute { .. }, State::Processed, false) => {
+                        account.held += amount;
+                        account.total += amount;
+                        movement.state = State::Disputed;
+                    }
+                    (Op::Resolve { .. }, State::Disputed, deposit) => {
+                        account.held -= amount;
+                        if deposit { account.available += amount } else { account.total -= amount }
+                        movement.state = State::Resolved;
+                    }
+                    (Op::Chargeback { .. }, State::Disputed, deposit) => {
+                        account.held -= amount;
+                        if deposit { account.total -= amount } else { account.available += amount }
+                        account.locked = true;
+                        movement.state = State::ChargedBack;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/*
+Description: Runs the stream through the engine and the model and checks every property
+Returns: a description of the first property that does not hold
+*/
+fn check(ops: &[Op], threads: usize) -> Result<(), String> {
+    let engine = PaymentsEngine::with_config(EngineConfig { threads, check_invariants: true, ..EngineConfig::default() });
+    engine
+        .process_transactions_from_reader(to_csv(ops).as_bytes())
+        .map_err(|err| format!("the run failed: {}", err))?;
+    let mut model = Model::default();
+    for op in ops {
+        model.apply(*op);
+    }
+
+    // Differential: the engine agrees with the model on every client
+    if engine.clients().len() != model.accounts.len() {
+        return Err(format!("engine has {} clients, model {}", engine.clients().len(), model.accounts.len()));
+    }
+    for (id, expected) in &model.accounts {
+        let client = engine.clients().get(id).ok_or(format!("client {} is missing", id))?;
+        let found = Account {
+            available: client.available().raw(),
+            held: client.held().raw(),
+            total: client.total().raw(),
+            locked: client.locked(),
+        };
+        if found != *expected {
+            return Err(format!("client {}: engine {:?}, model {:?}", id, found, expected));
+        }
+    }
+
+    // Conservation: every client's total is what was deposited, less what was withdrawn, with
+    // charged back movements reversed and disputed withdrawals held as a pending credit
+    let mut expected_totals: HashMap<u16, i64> = HashMap::new();
+    for movement in model.movements.values() {
+        let signed = if movement.deposit { movement.amount } else { -movement.amount };
+        let total = expected_totals.entry(movement.client).or_default();
+        *total += match movement.state {
+            State::Failed => 0,
+            State::Processed | State::Resolved => signed,
+            State::Disputed if movement.deposit => signed,
+            State::Disputed => 0,
+            State::ChargedBack => 0,
+        };
+    }
+    for (id, account) in &model.accounts {
+        let expected = expected_totals.get(id).copied().unwrap_or(0);
+        if account.total != expected || account.available + account.held != account.total || account.held < 0 {
+            return Err(format!("client {}: money is not conserved, {:?}, expected total {}", id, account, expected));
+        }
+    }
+
+    // Lock semantics: an account is locked exactly when one of its movements was charged back
+    for (id, account) in &model.accounts {
+        let charged_back = model.movements.values().any(|movement| movement.client == *id && movement.state == State::ChargedBack);
+        if account.locked != charged_back {
+            return Err(format!("client {}: locked is {} but charged back is {}", id, account.locked, charged_back));
+        }
+    }
+    Ok(())
+}
+
+/*
+Description: Shrinks a failing stream by removing ever smaller chunks of rows as long as the
+             property keeps failing, then returns the smallest stream that was found.
+Parameters:
+    ops: Vec<Op>  A stream for which property returns an error
+    property: Fn(&[Op]) -> Result<(), String>  The property that failed
+*/
+fn shrink<F>(mut ops: Vec<Op>, property: F) -> Vec<Op>
+where
+    F: Fn(&[Op]) -> Result<(), String>,
+{
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        let mut removed_any = false;
+        while start < ops.len() {
+            let end = (start + chunk).min(ops.len());
+            let candidate: Vec<Op> = ops[..start].iter().chain(&ops[end..]).copied().collect();
+            if property(&candidate).is_err() {
+                ops = candidate;
+                removed_any = true;
+            } else {
+                start += chunk;
+            }
+        }
+        if !removed_any {
+            chunk /= 2;
+        }
+    }
+    ops
+}
+
+fn cases() -> u64 {
+    std::env::var("ENGINE_PROPERTY_CASES").ok().and_then(|cases| cases.parse().ok()).unwrap_or(100)
+}
+
+/*
+    Runs the property over many seeds and panics with the shrunk csv of the first failure
+ */
+fn run_property<F>(ops: usize, clients: u16, property: F)
+where
+    F: Fn(&[Op]) -> Result<(), String>,
+{
+    for seed in 0..cases() {
+        let stream = generate(&mut Rng(seed), ops, clients);
+        if let Err(reason) = property(&stream) {
+            let minimal = shrink(stream, &property);
+            let reason = property(&minimal).err().unwrap_or(reason);
+            panic!("seed {} fails: {}\nminimal input:\n{}", seed, reason, to_csv(&minimal));
+        }
+    }
+}
+
+/*
+    i.   Few clients, so accounts get locked early and disputes pile up
+    ii.  Many clients with short histories
+    iii. Rows sharded between workers
+ */
+#[test_case(200, 3, 1)]
+#[test_case(200, 40, 1)]
+#[test_case(200, 5, 4)]
+fn test_engine_matches_model(ops: usize, clients: u16, threads: usize) {
+    run_property(ops, clients, |stream| check(stream, threads));
+}
+
+/*
+    The shrinker itself: a property that fails as soon as any account is locked shrinks to the
+    three rows that lock one.
+ */
+#[test]
+fn test_failures_shrink_to_minimal_csv() {
+    let locks_nothing = |stream: &[Op]| {
+        let mut model = Model::default();
+        stream.iter().for_each(|op| model.apply(*op));
+        match model.accounts.values().any(|account| account.locked) {
+            true => Err("an account was locked".to_string()),
+            false => Ok(()),
+        }
+    };
+    let stream = (0..cases())
+        .map(|seed| generate(&mut Rng(seed), 200, 3))
+        .find(|stream| locks_nothing(stream).is_err())
+        .expect("some stream charges back a deposit");
+
+    let minimal = shrink(stream, locks_nothing);
+    assert_eq!(minimal.len(), 3);
+    assert!(matches!(minimal[0], Op::Deposit { .. } | Op::Withdrawal { .. }));
+    assert!(matches!(minimal[1], Op::Dispute { .. }));
+    assert!(matches!(minimal[2], Op::Chargeback { .. }));
+    assert_eq!(to_csv(&minimal).lines().count(), 4);
+}
@@ -0,0 +1,267 @@
+use crate::config::{AmountRules, PrecisionPolicy};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/*
+    Fixed-point money type. Amounts are stored as a signed count of ten-thousandths of a unit so
+    four decimal places are represented exactly. f32 balances drifted after a few thousand
+    operations (100.1234 cannot be stored exactly), this keeps ledger totals correct to the last
+    digit. All arithmetic is checked, an overflow is returned as an error instead of wrapping.
+ */
+pub const DECIMAL_PLACES: u32 = 4;
+pub const SCALE: i64 = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    Overflow,
+    Invalid(String),
+    // More decimal places than the rules allow, with the policy set to reject
+    TooPrecise { places: usize, max: u32 },
+}
+
+/*
+    An amount read by Money::parse_amount. adjusted is set when the input had more decimal places
+    than allowed and was rounded or truncated to fit.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedAmount {
+    pub amount: Money,
+    pub adjusted: bool,
+}
+
+// Exponents beyond this can't give an amount the ledger can hold
+const MAX_EXPONENT: i64 = 40;
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::Overflow => write!(f, "amount overflow"),
+            MoneyError::Invalid(reason) => write!(f, "invalid amount: {}", reason),
+            MoneyError::TooPrecise { places, max } => {
+                write!(f, "amount has {} decimal places, at most {} are allowed", places, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    /*
+    Description: Creates an amount from a raw count of ten-thousandths, 1.5 is Money::from_raw(15000)
+    */
+    pub const fn from_raw(raw: i64) -> Self {
+        Money(raw)
+    }
+
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.0.checked_add(other.0).map(Money).ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.0.checked_sub(other.0).map(Money).ok_or(MoneyError::Overflow)
+    }
+}
+
+impl Money {
+    /*
+    Description: Reads an amount from the input under the given rules. Accepts plain decimals such as
+                 "100.1234", "-5" or ".5" and, when the rules allow it, exponent notation such as
+                 "1.5e2". NaN, infinity and anything else that isn't a number are invalid. An
+                 amount with more decimal places than allowed is rejected, rounded half to even or
+                 truncated, as the rules say.
+    Parameters:
+        input: &str  The amount as it was written
+        rules: &AmountRules  Allowed decimal places, exponent notation and the precision policy
+    */
+    pub fn parse_amount(input: &str, rules: &AmountRules) -> Result<ParsedAmount, MoneyError> {
+        let trimmed = input.trim();
+        let (negative, unsigned) = match trimmed.as_bytes().first() {
+            Some(b'-') => (true, &trimmed[1..]),
+            Some(b'+') => (false, &trimmed[1..]),
+            _ => (false, trimmed),
+        };
+        if matches!(unsigned.to_lowercase().as_str(), "nan" | "inf" | "infinity") {
+            return Err(MoneyError::Invalid(format!("{:?} is not a finite number", input)));
+        }
+        let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+            Some(_) if !rules.allow_exponent => {
+                return Err(MoneyError::Invalid(format!("{:?} uses exponent notation", input)));
+            }
+            Some(index) => {
+                let exponent = unsigned[index + 1..]
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|exponent| exponent.abs() <= MAX_EXPONENT)
+                    .ok_or_else(|| MoneyError::Invalid(format!("{:?} has an invalid exponent", input)))?;
+                (&unsigned[..index], exponent)
+            }
+            None => (unsigned, 0),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(MoneyError::Invalid(format!("{:?} is not a number", input)));
+        }
+        if !whole.bytes().all(|b| b.is_ascii_digit()) || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(MoneyError::Invalid(format!("{:?} is not a plain decimal number", input)));
+        }
+
+        // Move the decimal point by the exponent, 1.5e2 becomes 150 and 1.5e-2 becomes 0.015
+        let (whole, fraction) = if exponent == 0 {
+            (whole.to_string(), fraction.to_string())
+        } else {
+            let digits = format!("{}{}", whole, fraction);
+            let point = whole.len() as i64 + exponent;
+            if point <= 0 {
+                (String::new(), format!("{}{}", "0".repeat(point.unsigned_abs() as usize), digits))
+            } else if point as usize >= digits.len() {
+                (format!("{}{}", digits, "0".repeat(point as usize - digits.len())), String::new())
+            } else {
+                let (whole, fraction) = digits.split_at(point as usize);
+                (whole.to_string(), fraction.to_string())
+            }
+        };
+
+        let places = rules.max_decimal_places.min(DECIMAL_PLACES);
+        let significant = fraction.trim_end_matches('0');
+        let (kept, dropped) = significant.split_at(significant.len().min(places as usize));
+        let adjusted = !dropped.is_empty();
+        if adjusted && rules.excess_precision == PrecisionPolicy::Reject {
+            return Err(MoneyError::TooPrecise { places: significant.len(), max: places });
+        }
+
+        // Count in units of the last allowed place, then scale up to the ledger's four places
+        let mut units: i64 = 0;
+        for digit in whole.bytes().chain(kept.bytes()).chain(std::iter::repeat_n(b'0', places as usize - kept.len())) {
+            units = units
+                .checked_mul(10)
+                .and_then(|value| value.checked_add(i64::from(digit - b'0')))
+                .ok_or(MoneyError::Overflow)?;
+        }
+        if rules.excess_precision == PrecisionPolicy::RoundHalfEven && rounds_up(units, dropped) {
+            units = units.checked_add(1).ok_or(MoneyError::Overflow)?;
+        }
+        let raw = units
+            .checked_mul(10i64.pow(DECIMAL_PLACES - places))
+            .ok_or(MoneyError::Overflow)?;
+
+        Ok(ParsedAmount {
+            amount: Money(if negative { -raw } else { raw }),
+            adjusted,
+        })
+    }
+}
+
+/*
+Description: Whether dropping the digits rounds the kept units up under round half to even
+Parameters:
+    units: i64  The amount without the dropped digits
+    dropped: &str  The digits past the last allowed place, without trailing zeros
+*/
+fn rounds_up(units: i64, dropped: &str) -> bool {
+    match dropped.as_bytes().first() {
+        Some(b'5') if dropped.len() == 1 => units % 2 == 1,
+        Some(digit) => *digit >= b'5',
+        None => false,
+    }
+}
+
+/*
+    Parses a plain decimal string such as "100.1234", "-5" or ".5". Anything past four decimal
+    places, exponents, NaN and infinity are rejected rather than rounded away silently.
+ */
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Money::parse_amount(input, &AmountRules::default()).map(|parsed| parsed.amount)
+    }
+}
+
+/*
+    Formats with four decimal places by default. A precision in the format string ("{:.2}") is
+    honoured, values are rounded half away from zero when fewer places are requested.
+ */
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = f.precision().unwrap_or(DECIMAL_PLACES as usize);
+        let mut magnitude = u128::from(self.0.unsigned_abs());
+        let mut scale = SCALE as u128;
+
+        if precision < DECIMAL_PLACES as usize {
+            let divisor = 10u128.pow(DECIMAL_PLACES - precision as u32);
+            magnitude = (magnitude + divisor / 2) / divisor;
+            scale /= divisor;
+        }
+
+        let sign = if self.0 < 0 && magnitude != 0 { "-" } else { "" };
+        let whole = magnitude / scale;
+        if precision == 0 {
+            return write!(f, "{}{}", sign, whole);
+        }
+
+        let fraction = magnitude % scale;
+        let digits = precision.min(DECIMAL_PLACES as usize);
+        write!(f, "{}{}.{:0digits$}", sign, whole, fraction, digits = digits)?;
+        for _ in digits..precision {
+            f.write_str("0")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct MoneyVisitor;
+
+impl Visitor<'_> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal amount with at most four decimal places")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Money, E> {
+        value.parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Money, E> {
+        value.checked_mul(SCALE).map(Money).ok_or_else(|| E::custom(MoneyError::Overflow))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Money, E> {
+        i64::try_from(value)
+            .map_err(|_| E::custom(MoneyError::Overflow))
+            .and_then(|value| self.visit_i64(value))
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(MoneyVisitor)
+    }
+}
@@ -0,0 +1,148 @@
+use crate::client::Client;
+use crate::config::{AmountRules, PrecisionPolicy};
+use crate::money::{Money, MoneyError, ParsedAmount};
+use test_case::test_case;
+
+/*
+   I.    Whole number
+   II.   Four places past decimal
+   III.  Fewer than four places past decimal
+   IV.   Leading decimal point
+   V.    Negative amount
+   VI.   Surrounding whitespace
+ */
+#[test_case("100", 1_000_000)]
+#[test_case("100.1234", 1_001_234)]
+#[test_case("2.5", 25_000)]
+#[test_case(".5", 5_000)]
+#[test_case("-0.0001", -1)]
+#[test_case(" 42.42 ", 424_200)]
+#[allow(dead_code)]
+fn test_parse_money(input: &str, expected_raw: i64) {
+    assert_eq!(input.parse::<Money>().unwrap(), Money::from_raw(expected_raw));
+}
+
+/*
+   I.    Exponent notation
+   II.   Not a number
+   III.  Infinity
+   IV.   Empty field
+   V.    Two decimal points
+   VI.   Negative infinity
+ */
+#[test_case("1e30")]
+#[test_case("NaN")]
+#[test_case("inf")]
+#[test_case("")]
+#[test_case("12.3.4")]
+#[test_case("-Infinity")]
+#[allow(dead_code)]
+fn test_parse_invalid_money(input: &str) {
+    assert!(matches!(input.parse::<Money>(), Err(MoneyError::Invalid(_))));
+}
+
+#[test]
+fn test_parse_too_precise_money() {
+    assert_eq!("0.00001".parse::<Money>(), Err(MoneyError::TooPrecise { places: 5, max: 4 }));
+    // Trailing zeros are not extra places
+    assert_eq!("1.50000".parse::<Money>(), Ok(Money::from_raw(15_000)));
+}
+
+/*
+   I.    Fits, nothing to adjust
+   II.   Rounded half to even, tie goes down to the even digit
+   III.  Rounded half to even, tie goes up to the even digit
+   IV.   Rounded half to even, above the tie rounds up
+   V.    Truncated towards zero
+   VI.   Negative amounts truncate towards zero too
+   VII.  Fewer places allowed, rounded to cents
+   VIII. Exponent notation when it is allowed
+   IX.   Negative exponent that leaves too many places, rounded
+   X.    Rounding carries into the whole part
+ */
+#[test_case("1.2345", 4, false, PrecisionPolicy::RoundHalfEven, 12_345, false)]
+#[test_case("1.00005", 4, false, PrecisionPolicy::RoundHalfEven, 10_000, true)]
+#[test_case("1.00015", 4, false, PrecisionPolicy::RoundHalfEven, 10_002, true)]
+#[test_case("1.000051", 4, false, PrecisionPolicy::RoundHalfEven, 10_001, true)]
+#[test_case("1.99999", 4, false, PrecisionPolicy::Truncate, 19_999, true)]
+#[test_case("-1.99999", 4, false, PrecisionPolicy::Truncate, -19_999, true)]
+#[test_case("2.345", 2, false, PrecisionPolicy::RoundHalfEven, 23_400, true)]
+#[test_case("1.5e2", 4, true, PrecisionPolicy::Reject, 1_500_000, false)]
+#[test_case("15e-6", 4, true, PrecisionPolicy::RoundHalfEven, 0, true)]
+#[test_case("9.99995", 4, false, PrecisionPolicy::RoundHalfEven, 100_000, true)]
+fn test_parse_amount(input: &str, max_decimal_places: u32, allow_exponent: bool, excess_precision: PrecisionPolicy, expected_raw: i64, adjusted: bool) {
+    let rules = AmountRules { max_decimal_places, allow_exponent, excess_precision };
+    assert_eq!(
+        Money::parse_amount(input, &rules),
+        Ok(ParsedAmount { amount: Money::from_raw(expected_raw), adjusted })
+    );
+}
+
+/*
+   I.    Too many places under the reject policy
+   II.   Fewer places allowed under the reject policy
+   III.  Exponent notation when it isn't allowed
+   IV.   An exponent too large for any amount
+ */
+#[test_case("0.00001", 4, false, Err(MoneyError::TooPrecise { places: 5, max: 4 }))]
+#[test_case("2.345", 2, false, Err(MoneyError::TooPrecise { places: 3, max: 2 }))]
+#[test_case("1.5e2", 4, false, Err(MoneyError::Invalid("\"1.5e2\" uses exponent notation".to_string())))]
+#[test_case("1e400", 4, true, Err(MoneyError::Invalid("\"1e400\" has an invalid exponent".to_string())))]
+fn test_parse_amount_errors(input: &str, max_decimal_places: u32, allow_exponent: bool, expected: Result<ParsedAmount, MoneyError>) {
+    let rules = AmountRules { max_decimal_places, allow_exponent, ..AmountRules::default() };
+    assert_eq!(Money::parse_amount(input, &rules), expected);
+}
+
+/*
+   I.    Default of four places
+   II.   Requested precision is rounded
+   III.  Negative value between zero and one keeps its sign
+   IV.   Rounding to zero drops the sign
+ */
+#[test_case(1_001_234, "{}", "100.1234")]
+#[test_case(1_001_250, "{:.2}", "100.13")]
+#[test_case(-5_000, "{}", "-0.5000")]
+#[test_case(-1, "{:.2}", "0.00")]
+#[allow(dead_code)]
+fn test_display_money(raw: i64, format: &str, expected: &str) {
+    let money = Money::from_raw(raw);
+    let formatted = match format {
+        "{:.2}" => format!("{:.2}", money),
+        _ => format!("{}", money),
+    };
+    assert_eq!(formatted, expected);
+}
+
+#[test]
+fn test_money_overflow_is_an_error() {
+    assert_eq!(Money::from_raw(i64::MAX).checked_add(Money::from_raw(1)), Err(MoneyError::Overflow));
+    assert_eq!(Money::from_raw(i64::MIN).checked_sub(Money::from_raw(1)), Err(MoneyError::Overflow));
+    assert_eq!("922337203685478".parse::<Money>(), Err(MoneyError::Overflow));
+}
+
+/*
+    f32 balances drifted after a few thousand operations. The fixed point balance must land
+    exactly back on the starting value.
+ */
+#[test]
+fn test_ledger_does_not_drift() {
+    let mut client = Client::new(1);
+    let amount: Money = "100.1234".parse().unwrap();
+    for _ in 0..5_000 {
+        client.deposit(Some(amount)).unwrap();
+    }
+    for _ in 0..5_000 {
+        client.withdraw(Some(amount)).unwrap();
+    }
+    assert_eq!(client.available(), Money::ZERO);
+    assert_eq!(client.total(), Money::ZERO);
+}
+
+#[test]
+fn test_deposit_overflow_leaves_balance_untouched() {
+    let mut client = Client::new(1);
+    client.deposit(Some(Money::from_raw(i64::MAX))).unwrap();
+    assert!(client.deposit(Some(Money::from_raw(1))).is_err());
+    assert_eq!(client.available(), Money::from_raw(i64::MAX));
+    assert_eq!(client.total(), Money::from_raw(i64::MAX));
+}
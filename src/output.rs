@@ -0,0 +1,366 @@
+use crate::client::Client;
+use crate::config::{OutputFormat, OutputOptions, SortOrder};
+use crate::errors::EngineError;
+use crate::money::Money;
+use crate::transactions::PaymentsEngine;
+use csv::Writer;
+use dashmap::DashMap;
+use dashmap::mapref::multiple::RefMulti;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::io::Write;
+use std::time::Instant;
+use log::info;
+
+// Column names shared by every format, disputes is only added when it was asked for
+const COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+const DISPUTES_COLUMN: &str = "disputes";
+
+/*
+    One client as it is written out. Amounts are already formatted with the requested precision,
+    JSON writes them as strings so no consumer reads them back as a float.
+ */
+#[derive(Debug, Serialize)]
+pub struct AccountRow {
+    pub client: u16,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disputes: Option<Vec<OpenDispute>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenDispute {
+    pub tx: u32,
+    pub kind: String,
+    pub amount: String,
+}
+
+impl AccountRow {
+    pub fn new(client: &Client, options: &OutputOptions) -> Self {
+        let disputes = options.include_disputes.then(|| {
+            let mut disputes: Vec<OpenDispute> = client
+                .disputed_transactions()
+                .iter()
+                .map(|(tx, dispute)| OpenDispute {
+                    tx: *tx,
+                    kind: dispute.kind.to_string(),
+                    amount: format_amount(dispute.amount, options),
+                })
+                .collect();
+            disputes.sort_by_key(|dispute| dispute.tx);
+            disputes
+        });
+        Self {
+            client: client.id(),
+            available: format_amount(client.available(), options),
+            held: format_amount(client.held(), options),
+            total: format_amount(client.total(), options),
+            locked: client.locked(),
+            disputes,
+        }
+    }
+
+    /*
+    Description: The row as text cells for the csv and table formats. Open disputes go in a single
+                 cell as tx:kind:amount, separated by semicolons.
+    */
+    fn cells(&self) -> Vec<String> {
+        let mut cells = vec![
+            self.client.to_string(),
+            self.available.clone(),
+            self.held.clone(),
+            self.total.clone(),
+            self.locked.to_string(),
+        ];
+        if let Some(disputes) = &self.disputes {
+            let disputes: Vec<String> = disputes
+                .iter()
+                .map(|dispute| format!("{}:{}:{}", dispute.tx, dispute.kind, dispute.amount))
+                .collect();
+            cells.push(disputes.join(";"));
+        }
+        cells
+    }
+}
+
+/*
+    Writes the accounts at the end of a run in one format. Rows are passed in output order, a
+    writer that can stream writes them as they come and the others keep them until finish.
+ */
+pub trait OutputWriter {
+    fn write_account(&mut self, account: AccountRow) -> Result<(), EngineError>;
+
+    /*
+    Description: Writes whatever the format needs after the last row and flushes the output
+    */
+    fn finish(&mut self) -> Result<(), EngineError>;
+}
+
+pub struct CsvOutput<W: Write> {
+    writer: Writer<W>,
+}
+
+impl<W: Write> CsvOutput<W> {
+    pub fn new(output: W, include_disputes: bool) -> Result<Self, EngineError> {
+        let mut writer = Writer::from_writer(output);
+        writer.write_record(header(include_disputes))?;
+        Ok(Self { writer })
+    }
+}
+
+impl<W: Write> OutputWriter for CsvOutput<W> {
+    fn write_account(&mut self, account: AccountRow) -> Result<(), EngineError> {
+        self.writer.write_record(account.cells())?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EngineError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/*
+    A JSON array, written one element at a time so the clients never have to be held twice
+ */
+pub struct JsonOutput<W: Write> {
+    output: W,
+    rows: usize,
+}
+
+impl<W: Write> JsonOutput<W> {
+    pub fn new(output: W) -> Self {
+        Self { output, rows: 0 }
+    }
+}
+
+impl<W: Write> OutputWriter for JsonOutput<W> {
+    fn write_account(&mut self, account: AccountRow) -> Result<(), EngineError> {
+        self.output.write_all(if self.rows == 0 { b"[\n  " } else { b",\n  " })?;
+        serde_json::to_writer(&mut self.output, &account).map_err(json_error)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EngineError> {
+        self.output.write_all(if self.rows == 0 { b"[]\n" } else { b"\n]\n" })?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+pub struct JsonlOutput<W: Write> {
+    output: W,
+}
+
+impl<W: Write> JsonlOutput<W> {
+    pub fn new(output: W) -> Self {
+        Self { output }
+    }
+}
+
+impl<W: Write> OutputWriter for JsonlOutput<W> {
+    fn write_account(&mut self, account: AccountRow) -> Result<(), EngineError> {
+        serde_json::to_writer(&mut self.output, &account).map_err(json_error)?;
+        self.output.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EngineError> {
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/*
+    Aligned columns separated by |, numbers are right aligned. The column widths depend on every
+    row, so the rows are kept until finish.
+ */
+pub struct TableOutput<W: Write> {
+    output: W,
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl<W: Write> TableOutput<W> {
+    pub fn new(output: W, include_disputes: bool) -> Self {
+        Self {
+            output,
+            header: header(include_disputes).iter().map(|column| column.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> OutputWriter for TableOutput<W> {
+    fn write_account(&mut self, account: AccountRow) -> Result<(), EngineError> {
+        self.rows.push(account.cells());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EngineError> {
+        let widths: Vec<usize> = (0..self.header.len())
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .chain(std::iter::once(&self.header))
+                    .map(|row| row[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        // The amount columns are right aligned, client, locked and disputes are text
+        let right_aligned = |column: usize| (1..=3).contains(&column);
+        let write_row = |output: &mut W, row: &[String]| -> Result<(), EngineError> {
+            let cells: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(column, cell)| match right_aligned(column) {
+                    true => format!("{:>width$}", cell, width = widths[column]),
+                    false => format!("{:<width$}", cell, width = widths[column]),
+                })
+                .collect();
+            writeln!(output, "{}", cells.join(" | ").trim_end())?;
+            Ok(())
+        };
+        write_row(&mut self.output, &self.header)?;
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        writeln!(self.output, "{}", rule.join("-+-"))?;
+        for row in &self.rows {
+            write_row(&mut self.output, row)?;
+        }
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/*
+    One JSON object with an array per column, so a loader can take a whole column at once:
+        {"client":[1,2],"available":["1.0000","2.0000"],...}
+ */
+pub struct ColumnarOutput<W: Write> {
+    output: W,
+    columns: Columns,
+}
+
+#[derive(Default, Serialize)]
+struct Columns {
+    client: Vec<u16>,
+    available: Vec<String>,
+    held: Vec<String>,
+    total: Vec<String>,
+    locked: Vec<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disputes: Option<Vec<Vec<OpenDispute>>>,
+}
+
+impl<W: Write> ColumnarOutput<W> {
+    pub fn new(output: W, include_disputes: bool) -> Self {
+        Self {
+            output,
+            columns: Columns {
+                disputes: include_disputes.then(Vec::new),
+                ..Columns::default()
+            },
+        }
+    }
+}
+
+impl<W: Write> OutputWriter for ColumnarOutput<W> {
+    fn write_account(&mut self, account: AccountRow) -> Result<(), EngineError> {
+        self.columns.client.push(account.client);
+        self.columns.available.push(account.available);
+        self.columns.held.push(account.held);
+        self.columns.total.push(account.total);
+        self.columns.locked.push(account.locked);
+        if let (Some(column), Some(disputes)) = (&mut self.columns.disputes, account.disputes) {
+            column.push(disputes);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EngineError> {
+        serde_json::to_writer(&mut self.output, &self.columns).map_err(json_error)?;
+        self.output.write_all(b"\n")?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/*
+Description: Creates the writer for the format in the options
+Parameters:
+    options: &OutputOptions  Format and whether open disputes are included
+    output: W  Where the accounts are written
+*/
+pub fn output_writer<'a, W: Write + 'a>(options: &OutputOptions, output: W) -> Result<Box<dyn OutputWriter + 'a>, EngineError> {
+    Ok(match options.format {
+        OutputFormat::Csv => Box::new(CsvOutput::new(output, options.include_disputes)?),
+        OutputFormat::Json => Box::new(JsonOutput::new(output)),
+        OutputFormat::Jsonl => Box::new(JsonlOutput::new(output)),
+        OutputFormat::Table => Box::new(TableOutput::new(output, options.include_disputes)),
+        OutputFormat::Columnar => Box::new(ColumnarOutput::new(output, options.include_disputes)),
+    })
+}
+
+/*
+Description: Writes every client's balances, main passes stdout. Negative balances keep their sign,
+             they can happen when disputes are allowed to drive available below zero.
+Parameters:
+    engine: &PaymentsEngine  The engine whose clients are written
+    options: &OutputOptions  Format, order of the rows and how amounts are formatted
+    output: W  Where the accounts are written
+*/
+pub fn write_accounts<W: Write>(engine: &PaymentsEngine, options: &OutputOptions, output: W) -> Result<(), EngineError> {
+    let start_time = Instant::now();
+    let mut writer = output_writer(options, output)?;
+    for client_entry in sorted_clients(engine.clients(), options.sort) {
+        writer.write_account(AccountRow::new(client_entry.value(), options))?;
+    }
+    writer.finish()?;
+    info!(
+        "write_accounts completed in {:.2?} seconds.",
+        start_time.elapsed()
+    );
+    Ok(())
+}
+
+/*
+Description: Formats an amount with the precision of the options, trimming trailing zeros if asked
+*/
+pub fn format_amount(amount: Money, options: &OutputOptions) -> String {
+    let formatted = format!("{:.*}", options.precision, amount);
+    if options.trim_zeros && formatted.contains('.') {
+        formatted.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        formatted
+    }
+}
+
+/*
+Description: Collects the clients in the requested order, ties are broken by client id
+*/
+fn sorted_clients(client_map: &DashMap<u16, Client>, sort: SortOrder) -> Vec<RefMulti<'_, u16, Client>> {
+    let mut clients: Vec<_> = client_map.iter().collect();
+    match sort {
+        SortOrder::ClientId => clients.sort_by_key(|client| client.id()),
+        SortOrder::Total => clients.sort_by_key(|client| (Reverse(client.total()), client.id())),
+        SortOrder::LockedFirst => clients.sort_by_key(|client| (!client.locked(), client.id())),
+    }
+    clients
+}
+
+fn header(include_disputes: bool) -> Vec<&'static str> {
+    let mut header = COLUMNS.to_vec();
+    if include_disputes {
+        header.push(DISPUTES_COLUMN);
+    }
+    header
+}
+
+fn json_error(err: serde_json::Error) -> EngineError {
+    EngineError::Io(err.to_string())
+}
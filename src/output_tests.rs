@@ -0,0 +1,92 @@
+use crate::config::{DisputePolicy, EngineConfig, OutputFormat, OutputOptions, SortOrder};
+use crate::money::Money;
+use crate::transactions::PaymentsEngine;
+use crate::output::{format_amount, write_accounts};
+use std::fs;
+use std::path::Path;
+use test_case::test_case;
+
+/*
+    i.    Four places by default
+    ii.   Fewer places round half away from zero
+    iii.  Negative amounts keep their sign
+    iv.   Rounding to zero drops the sign
+    v.    More places are padded
+    vi.   No fraction at all
+    vii.  Trimmed zeros
+    viii. Trimming a whole number drops the point
+    ix.   Trimming after rounding
+ */
+#[test_case(12_345, 4, false, "1.2345")]
+#[test_case(20_050, 2, false, "2.01")]
+#[test_case(-805_000, 2, false, "-80.50")]
+#[test_case(-1, 2, false, "0.00")]
+#[test_case(15_000, 6, false, "1.500000")]
+#[test_case(15_000, 0, false, "2")]
+#[test_case(-805_000, 4, true, "-80.5")]
+#[test_case(50_000, 4, true, "5")]
+#[test_case(12_999, 2, true, "1.3")]
+fn test_format_amount(raw: i64, precision: usize, trim_zeros: bool, expected: &str) {
+    let options = OutputOptions { precision, trim_zeros, ..OutputOptions::default() };
+    assert_eq!(format_amount(Money::from_raw(raw), &options), expected);
+}
+
+/*
+    Golden files for the accounts csv. tests/golden/transactions.csv is processed with disputes
+    allowed to go negative, so the output has negative, held, locked and zero balances, and the
+    result is compared byte for byte with the expected file. Set UPDATE_GOLDEN=1 to rewrite the
+    expected files after an intended change to the output.
+    i.    The defaults: csv by client id with four places
+    ii.   Two places, largest total first
+    iii.  Trimmed zeros, locked accounts first
+    iv.   csv with the open disputes
+    v.    JSON array
+    vi.   JSON lines with the open disputes
+    vii.  Table with the open disputes
+    viii. Columnar with the open disputes
+ */
+#[test_case(OutputOptions::default(), "accounts.csv")]
+#[test_case(OutputOptions { sort: SortOrder::Total, precision: 2, ..OutputOptions::default() }, "accounts_precision_2.csv")]
+#[test_case(OutputOptions { sort: SortOrder::LockedFirst, trim_zeros: true, ..OutputOptions::default() }, "accounts_trimmed.csv")]
+#[test_case(OutputOptions { include_disputes: true, ..OutputOptions::default() }, "accounts_disputes.csv")]
+#[test_case(OutputOptions { format: OutputFormat::Json, ..OutputOptions::default() }, "accounts.json")]
+#[test_case(OutputOptions { format: OutputFormat::Jsonl, include_disputes: true, ..OutputOptions::default() }, "accounts.jsonl")]
+#[test_case(OutputOptions { format: OutputFormat::Table, include_disputes: true, ..OutputOptions::default() }, "accounts_table.txt")]
+#[test_case(OutputOptions { format: OutputFormat::Columnar, include_disputes: true, ..OutputOptions::default() }, "accounts_columnar.json")]
+fn test_accounts_golden(options: OutputOptions, golden: &str) {
+    let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let input = fs::read(golden_dir.join("transactions.csv")).unwrap();
+    let engine = PaymentsEngine::with_config(EngineConfig {
+        dispute_policy: DisputePolicy::AllowNegative,
+        ..EngineConfig::default()
+    });
+    engine.process_transactions_from_reader(input.as_slice()).unwrap();
+
+    let mut output = Vec::new();
+    write_accounts(&engine, &options, &mut output).unwrap();
+    let golden_path = golden_dir.join(golden);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden_path, &output).unwrap();
+    }
+    assert_eq!(String::from_utf8(output).unwrap(), fs::read_to_string(golden_path).unwrap());
+}
+
+/*
+    Every format writes something valid for no clients at all
+    i.   csv: only the header
+    ii.  JSON: an empty array
+    iii. JSON lines: nothing
+    iv.  Table: the header and the rule
+    v.   Columnar: empty columns
+ */
+#[test_case(OutputFormat::Csv, "client,available,held,total,locked\n")]
+#[test_case(OutputFormat::Json, "[]\n")]
+#[test_case(OutputFormat::Jsonl, "")]
+#[test_case(OutputFormat::Table, "client | available | held | total | locked\n-------+-----------+------+-------+-------\n")]
+#[test_case(OutputFormat::Columnar, "{\"client\":[],\"available\":[],\"held\":[],\"total\":[],\"locked\":[]}\n")]
+fn test_no_accounts(format: OutputFormat, expected: &str) {
+    let engine = PaymentsEngine::new();
+    let mut output = Vec::new();
+    write_accounts(&engine, &OutputOptions { format, ..OutputOptions::default() }, &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
@@ -0,0 +1,99 @@
+use crate::errors::EngineError;
+use crate::transactions::column_value;
+use csv::Writer;
+use std::fs::File;
+use std::io::Write;
+
+/*
+    A row that was not applied. The raw record is kept as it was read so the reconciliation team
+    can find and fix the original row. file names the input the row was read from, it is None for
+    input that isn't a file. tx and client are only present when they could be read.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub file: Option<String>,
+    pub line: u64,
+    pub record: String,
+    pub tx: Option<u32>,
+    pub client: Option<u16>,
+    pub reason: EngineError,
+}
+
+impl Rejection {
+    /*
+    Description: Builds a rejection from the csv record the error came from. tx and client are
+                 read on a best effort basis, they are left empty when the row is malformed.
+    Parameters:
+        file: Option<&str>  Name of the input file, None when the input isn't a file
+        line: u64  Line number of the row in the input
+        record: Option<&csv::StringRecord>  The raw row, None when it could not be read at all
+        header: &csv::StringRecord  The column names of the input, tx and client are found by name
+        reason: EngineError  Why the row was not applied
+    */
+    pub fn from_record(file: Option<&str>, line: u64, record: Option<&csv::StringRecord>, header: &csv::StringRecord, reason: EngineError) -> Self {
+        Self {
+            file: file.map(str::to_string),
+            line,
+            record: record.map_or_else(String::new, encode_record),
+            tx: record.and_then(|record| column_value(record, header, "tx")).and_then(|tx| tx.parse().ok()),
+            client: record.and_then(|record| column_value(record, header, "client")).and_then(|client| client.parse().ok()),
+            reason,
+        }
+    }
+}
+
+/*
+Description: Writes a record back as one csv line without the line terminator. Fields holding a
+             comma, a quote or a line break are quoted the same way they have to be in the input,
+             so the line can be parsed back into the same fields.
+Parameters:
+    record: &csv::StringRecord  The row to encode
+*/
+pub(crate) fn encode_record(record: &csv::StringRecord) -> String {
+    let mut writer = Writer::from_writer(Vec::new());
+    if writer.write_record(record).is_err() {
+        return String::new();
+    }
+    let bytes = writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&bytes).trim_end_matches(['\r', '\n']).to_string()
+}
+
+/*
+    Machine readable report of every row that was not applied, written as csv with the columns
+    file, line, record, tx, client, reason, detail. file is left empty for input that isn't a
+    file. reason is the stable EngineError code, detail is the
+    human readable message.
+ */
+pub struct RejectsWriter {
+    writer: Writer<Box<dyn Write + Send>>,
+}
+
+impl RejectsWriter {
+    pub fn new(output: Box<dyn Write + Send>) -> Result<Self, EngineError> {
+        let mut writer = Writer::from_writer(output);
+        writer.write_record(["file", "line", "record", "tx", "client", "reason", "detail"])?;
+        Ok(Self { writer })
+    }
+
+    pub fn from_path(path: &str) -> Result<Self, EngineError> {
+        Self::new(Box::new(File::create(path)?))
+    }
+
+    pub fn write(&mut self, rejection: &Rejection) -> Result<(), EngineError> {
+        self.writer.write_record([
+            rejection.file.clone().unwrap_or_default(),
+            rejection.line.to_string(),
+            rejection.record.clone(),
+            rejection.tx.map_or_else(String::new, |tx| tx.to_string()),
+            rejection.client.map_or_else(String::new, |client| client.to_string()),
+            rejection.reason.code().to_string(),
+            rejection.reason.to_string(),
+        ])?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), EngineError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use crate::client::{Client, ClientState, DisputedTransaction};
+use crate::errors::EngineError;
+use crate::transactions::{LedgerEntry, PaymentsEngine, TxState};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use log::info;
+
+// Bumped whenever the layout of a snapshot changes
+const SNAPSHOT_VERSION: u32 = 1;
+
+/*
+    One line of a snapshot. A snapshot is a JSON lines file that starts with the version, followed
+    by every client and every stored deposit and withdrawal:
+        {"version":1}
+        {"client":{"id":1,"available":"5.0000","held":"0.0000",...}}
+        {"transaction":{"tx":1,"entry":{"client":1,"kind":"Deposit","amount":"5.0000",...}}}
+    Amounts are written as decimal strings so they round trip exactly.
+ */
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SnapshotRecord<C> {
+    Version(u32),
+    Client(C),
+    Transaction { tx: u32, entry: LedgerEntry },
+}
+
+/*
+Description: Writes the full state of the engine: balances, locked flags, open disputes and the
+             transaction history, so a later run can continue from it.
+Parameters:
+    engine: &PaymentsEngine  The engine to save, it should not be processing at the same time
+    output: W  Where the snapshot is written
+*/
+pub fn save_state<W: Write>(engine: &PaymentsEngine, output: W) -> Result<(), EngineError> {
+    let mut output = BufWriter::new(output);
+    write_record(&mut output, &SnapshotRecord::<&Client>::Version(SNAPSHOT_VERSION))?;
+    for client in engine.clients().iter() {
+        write_record(&mut output, &SnapshotRecord::Client(client.value()))?;
+    }
+    engine.transaction_store().for_each(&mut |tx, entry| {
+        write_record(&mut output, &SnapshotRecord::<&Client>::Transaction { tx, entry })
+    })?;
+    output.flush()?;
+    Ok(())
+}
+
+/*
+Description: Saves the state to a file. The snapshot is written next to the target first and then
+             renamed over it, so a failed run never leaves yesterday's state half overwritten.
+*/
+pub fn save_state_to_path(engine: &PaymentsEngine, path: &str) -> Result<(), EngineError> {
+    let partial_path = format!("{}.partial", path);
+    save_state(engine, File::create(&partial_path)?)?;
+    fs::rename(&partial_path, path)?;
+    info!("Saved state of {} clients to {}", engine.clients().len(), path);
+    Ok(())
+}
+
+/*
+Description: Loads a snapshot written by save_state into a fresh engine, before any transactions
+             are processed. Each client is checked on the way in, it has to pass the same
+             invariants that are checked after every operation. Once everything is read the open
+             disputes of the clients have to be exactly the transactions in the Disputed state,
+             with the same client, kind and amount.
+Parameters:
+    engine: &PaymentsEngine  The engine to load into, with the transaction store it will use set
+    input: R  The snapshot
+*/
+pub fn load_state<R: Read>(engine: &PaymentsEngine, input: R) -> Result<(), EngineError> {
+    let mut version = None;
+    // Line of every client and of every transaction in the Disputed state, to report a mismatch
+    let mut client_lines = BTreeMap::new();
+    let mut disputed = BTreeMap::new();
+    for (index, line) in BufReader::new(input).lines().enumerate() {
+        let line_number = index as u64 + 1;
+        let invalid = |reason: String| EngineError::InvalidSnapshot { line: line_number, reason };
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: SnapshotRecord<ClientState> = serde_json::from_str(&line).map_err(|err| invalid(err.to_string()))?;
+        match (record, version) {
+            (SnapshotRecord::Version(found), None) if found == SNAPSHOT_VERSION => version = Some(found),
+            (SnapshotRecord::Version(found), None) => {
+                return Err(invalid(format!("unsupported snapshot version {}", found)));
+            }
+            (_, None) => return Err(invalid("the snapshot has to start with its version".to_string())),
+            (SnapshotRecord::Version(_), Some(_)) => return Err(invalid("version appears twice".to_string())),
+            (SnapshotRecord::Client(client), Some(_)) => {
+                let client = Client::from(client);
+                check_client(&client).map_err(invalid)?;
+                let id = client.id();
+                if engine.clients().insert(id, client).is_some() {
+                    return Err(invalid(format!("client {} appears twice", id)));
+                }
+                client_lines.insert(id, line_number);
+            }
+            (SnapshotRecord::Transaction { tx, entry }, Some(_)) => {
+                if !engine.transaction_store().insert(tx, entry)? {
+                    return Err(invalid(format!("transaction {} appears twice", tx)));
+                }
+                if entry.state() == TxState::Disputed {
+                    disputed.insert(tx, (line_number, entry));
+                }
+            }
+        }
+    }
+    if version.is_none() {
+        return Err(EngineError::InvalidSnapshot { line: 0, reason: "the snapshot is empty".to_string() });
+    }
+    check_disputes(engine, &client_lines, &disputed)
+}
+
+/*
+Description: Checks that the open disputes of the clients and the disputed transactions of the
+             history agree. A dispute the history doesn't know about, or a disputed transaction
+             no client holds, could never be resolved or charged back in a later run.
+Parameters:
+    engine: &PaymentsEngine  The engine the snapshot was loaded into
+    client_lines: &BTreeMap<u16, u64>  Line of each client in the snapshot
+    disputed: &BTreeMap<u32, (u64, LedgerEntry)>  Line and entry of each transaction in the Disputed state
+*/
+fn check_disputes(engine: &PaymentsEngine, client_lines: &BTreeMap<u16, u64>, disputed: &BTreeMap<u32, (u64, LedgerEntry)>) -> Result<(), EngineError> {
+    for (&id, &line) in client_lines {
+        let mut open: Vec<_> = engine
+            .clients()
+            .get(&id)
+            .map(|client| client.disputed_transactions().clone().into_iter().collect())
+            .unwrap_or_default();
+        open.sort_unstable_by_key(|&(tx, _)| tx);
+        for (tx, dispute) in open {
+            let matches = disputed.get(&tx).is_some_and(|(_, entry)| {
+                entry.client() == id && DisputedTransaction { kind: entry.kind(), amount: entry.amount() } == dispute
+            });
+            if !matches {
+                return Err(EngineError::InvalidSnapshot {
+                    line,
+                    reason: format!("client {} has an open dispute on transaction {} that the history doesn't match", id, tx),
+                });
+            }
+        }
+    }
+    for (&tx, &(line, entry)) in disputed {
+        let held = engine
+            .clients()
+            .get(&entry.client())
+            .is_some_and(|client| client.disputed_transactions().contains_key(&tx));
+        if !held {
+            return Err(EngineError::InvalidSnapshot {
+                line,
+                reason: format!("transaction {} is disputed but client {} has no open dispute on it", tx, entry.client()),
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn load_state_from_path(engine: &PaymentsEngine, path: &str) -> Result<(), EngineError> {
+    load_state(engine, File::open(path)?)?;
+    info!("Loaded state of {} clients from {}", engine.clients().len(), path);
+    Ok(())
+}
+
+fn write_record<W: Write>(output: &mut W, record: &SnapshotRecord<&Client>) -> Result<(), EngineError> {
+    serde_json::to_writer(&mut *output, record).map_err(|err| EngineError::Io(err.to_string()))?;
+    output.write_all(b"\n")?;
+    Ok(())
+}
+
+fn check_client(client: &Client) -> Result<(), String> {
+    client
+        .check_invariants()
+        .map_err(|reason| format!("client {}: {}", client.id(), reason))
+}
@@ -0,0 +1,119 @@
+use crate::config::EngineConfig;
+use crate::errors::EngineError;
+use crate::money::Money;
+use crate::snapshot::*;
+use crate::store::DiskStore;
+use crate::transactions::*;
+use crate::transactions_tests::balances;
+use test_case::test_case;
+
+fn run(engine: &PaymentsEngine, rows: &str) -> RunSummary {
+    engine.process_transactions_from_reader(format!("type,client,tx,amount\n{}", rows).as_bytes()).unwrap()
+}
+
+fn restore(snapshot: &[u8]) -> PaymentsEngine {
+    let engine = PaymentsEngine::new();
+    load_state(&engine, snapshot).unwrap();
+    engine
+}
+
+/*
+    Yesterday's run leaves a deposit under dispute and a deposit that can still be disputed.
+    Today's file resolves the open dispute and disputes the other deposit, the balances continue
+    from where yesterday stopped.
+ */
+#[test]
+fn test_state_carries_over_to_the_next_run() {
+    let yesterday = PaymentsEngine::new();
+    run(&yesterday, "deposit,1,1,10.0\ndeposit,1,2,5.5\ndispute,1,1,\ndeposit,2,3,1.0\nwithdrawal,2,4,1.0\n");
+    let mut snapshot = Vec::new();
+    save_state(&yesterday, &mut snapshot).unwrap();
+
+    let today = restore(&snapshot);
+    assert_eq!(balances(&today), balances(&yesterday));
+    assert_eq!(today.clients().get(&1).unwrap().disputed_transactions().len(), 1);
+
+    let summary = run(&today, "resolve,1,1,\ndispute,1,2,\ndeposit,3,1,1.0\n");
+    assert_eq!(summary.rejections.get("duplicate_tx"), Some(&1));
+    let client = today.clients().get(&1).unwrap();
+    assert_eq!(client.available(), "10".parse::<Money>().unwrap());
+    assert_eq!(client.held(), "5.5".parse::<Money>().unwrap());
+}
+
+#[test]
+fn test_locked_account_stays_locked() {
+    let yesterday = PaymentsEngine::new();
+    run(&yesterday, "deposit,1,1,10.0\ndeposit,1,2,5.0\ndispute,1,1,\nchargeback,1,1,\n");
+    let mut snapshot = Vec::new();
+    save_state(&yesterday, &mut snapshot).unwrap();
+
+    let today = restore(&snapshot);
+    let summary = run(&today, "deposit,1,3,1.0\n");
+    assert_eq!(summary.rejections.get("account_locked"), Some(&1));
+    assert!(today.clients().get(&1).unwrap().locked());
+}
+
+/*
+    A snapshot of an engine with its history on disk loads into an in-memory engine and back.
+ */
+#[test]
+fn test_snapshot_with_disk_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut on_disk = PaymentsEngine::with_config(EngineConfig::default());
+    on_disk.set_transaction_store(Box::new(DiskStore::create(dir.path().join("history.bin")).unwrap()));
+    run(&on_disk, "deposit,1,1,10.0\ndeposit,2,9000,3.0\ndispute,2,9000,\n");
+    let mut snapshot = Vec::new();
+    save_state(&on_disk, &mut snapshot).unwrap();
+
+    let in_memory = restore(&snapshot);
+    assert_eq!(balances(&in_memory), balances(&on_disk));
+    assert_eq!(in_memory.transaction_store().get(9000).unwrap().unwrap().state, TxState::Disputed);
+}
+
+#[test]
+fn test_save_state_to_path_replaces_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("state.jsonl");
+    let path = path.to_str().unwrap();
+    let engine = PaymentsEngine::new();
+    run(&engine, "deposit,1,1,10.0\n");
+    save_state_to_path(&engine, path).unwrap();
+    run(&engine, "deposit,1,2,10.0\n");
+    save_state_to_path(&engine, path).unwrap();
+
+    let restored = PaymentsEngine::new();
+    load_state_from_path(&restored, path).unwrap();
+    assert_eq!(restored.clients().get(&1).unwrap().total(), "20".parse::<Money>().unwrap());
+    assert!(!dir.path().join("state.jsonl.partial").exists());
+}
+
+/*
+    Snapshots that can't be trusted are refused with the line that is wrong:
+    i. No version line
+    ii. A version this build doesn't know
+    iii. total that is not available plus held
+    iv. held that doesn't match the open disputes
+    v. A tx id that appears twice
+    vi. A line that is not a snapshot record
+    vii. An open dispute on a transaction the history has as processed
+    viii. A disputed transaction no client has an open dispute on
+    ix. An open dispute with another amount than the disputed transaction
+    x. An open dispute on another client's transaction
+ */
+#[test_case("{\"client\":{\"id\":1,\"available\":\"1\",\"held\":\"0\",\"total\":\"1\",\"locked\":false,\"disputed_transactions\":{}}}", 1)]
+#[test_case("{\"version\":99}", 1)]
+#[test_case("{\"version\":1}\n{\"client\":{\"id\":1,\"available\":\"1\",\"held\":\"0\",\"total\":\"2\",\"locked\":false,\"disputed_transactions\":{}}}", 2)]
+#[test_case("{\"version\":1}\n{\"client\":{\"id\":1,\"available\":\"1\",\"held\":\"1\",\"total\":\"2\",\"locked\":false,\"disputed_transactions\":{}}}", 2)]
+#[test_case("{\"version\":1}\n{\"transaction\":{\"tx\":1,\"entry\":{\"client\":1,\"kind\":\"Deposit\",\"amount\":\"1\",\"state\":\"Processed\"}}}\n{\"transaction\":{\"tx\":1,\"entry\":{\"client\":1,\"kind\":\"Deposit\",\"amount\":\"1\",\"state\":\"Processed\"}}}", 3)]
+#[test_case("{\"version\":1}\nclient,available", 2)]
+#[test_case("{\"version\":1}\n{\"client\":{\"id\":1,\"available\":\"0\",\"held\":\"1\",\"total\":\"1\",\"locked\":false,\"disputed_transactions\":{\"1\":{\"kind\":\"Deposit\",\"amount\":\"1\"}}}}\n{\"transaction\":{\"tx\":1,\"entry\":{\"client\":1,\"kind\":\"Deposit\",\"amount\":\"1\",\"state\":\"Processed\"}}}", 2)]
+#[test_case("{\"version\":1}\n{\"client\":{\"id\":1,\"available\":\"1\",\"held\":\"0\",\"total\":\"1\",\"locked\":false,\"disputed_transactions\":{}}}\n{\"transaction\":{\"tx\":1,\"entry\":{\"client\":1,\"kind\":\"Deposit\",\"amount\":\"1\",\"state\":\"Disputed\"}}}", 3)]
+#[test_case("{\"version\":1}\n{\"client\":{\"id\":1,\"available\":\"0\",\"held\":\"1\",\"total\":\"1\",\"locked\":false,\"disputed_transactions\":{\"1\":{\"kind\":\"Deposit\",\"amount\":\"1\"}}}}\n{\"transaction\":{\"tx\":1,\"entry\":{\"client\":1,\"kind\":\"Deposit\",\"amount\":\"2\",\"state\":\"Disputed\"}}}", 2)]
+#[test_case("{\"version\":1}\n{\"client\":{\"id\":1,\"available\":\"0\",\"held\":\"1\",\"total\":\"1\",\"locked\":false,\"disputed_transactions\":{\"1\":{\"kind\":\"Deposit\",\"amount\":\"1\"}}}}\n{\"transaction\":{\"tx\":1,\"entry\":{\"client\":2,\"kind\":\"Deposit\",\"amount\":\"1\",\"state\":\"Disputed\"}}}", 2)]
+fn test_invalid_snapshot(snapshot: &str, line: u64) {
+    let engine = PaymentsEngine::new();
+    match load_state(&engine, snapshot.as_bytes()) {
+        Err(EngineError::InvalidSnapshot { line: found, .. }) => assert_eq!(found, line),
+        other => panic!("expected an invalid snapshot, got {:?}", other),
+    }
+}
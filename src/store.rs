@@ -0,0 +1,283 @@
+use crate::errors::EngineError;
+use crate::money::Money;
+use crate::transactions::{EntryKind, LedgerEntry, TxState};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use log::warn;
+
+/*
+    Where the engine keeps the deposits and withdrawals that disputes look up. The engine only
+    needs to claim an id, read an entry back and move it through the dispute lifecycle, so a store
+    can keep the history anywhere as long as any tx id of the run can still be found.
+    Implementations are shared between the worker threads and have to do their own locking.
+ */
+pub trait TransactionStore: Send + Sync {
+    /*
+    Description: Stores the entry under its tx id unless the id is already taken
+    Returns: true when the entry was stored, false when the id was taken
+    */
+    fn insert(&self, tx: u32, entry: LedgerEntry) -> Result<bool, EngineError>;
+
+    fn get(&self, tx: u32) -> Result<Option<LedgerEntry>, EngineError>;
+
+    /*
+    Description: Moves a stored entry to a new state, the caller checks the transition first
+    */
+    fn set_state(&self, tx: u32, state: TxState) -> Result<(), EngineError>;
+
+    /*
+    Description: Calls visit with every stored entry, used to save the engine state. Entries are
+                 visited one at a time so a store doesn't have to load its whole history.
+    */
+    fn for_each(&self, visit: &mut dyn FnMut(u32, LedgerEntry) -> Result<(), EngineError>) -> Result<(), EngineError>;
+}
+
+/*
+    Keeps the whole history in memory. Fast, but every deposit and withdrawal of the run stays
+    resident, which is too much for inputs with hundreds of millions of rows. This is the default.
+ */
+#[derive(Default)]
+pub struct InMemoryStore {
+    transactions: DashMap<u32, LedgerEntry>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TransactionStore for InMemoryStore {
+    fn insert(&self, tx: u32, entry: LedgerEntry) -> Result<bool, EngineError> {
+        match self.transactions.entry(tx) {
+            Entry::Vacant(vacant) => {
+                vacant.insert(entry);
+                Ok(true)
+            }
+            Entry::Occupied(_) => Ok(false),
+        }
+    }
+
+    fn get(&self, tx: u32) -> Result<Option<LedgerEntry>, EngineError> {
+        Ok(self.transactions.get(&tx).map(|entry| *entry))
+    }
+
+    fn set_state(&self, tx: u32, state: TxState) -> Result<(), EngineError> {
+        let mut entry = self.transactions.get_mut(&tx).ok_or(EngineError::UnknownTx { tx })?;
+        entry.state = state;
+        Ok(())
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(u32, LedgerEntry) -> Result<(), EngineError>) -> Result<(), EngineError> {
+        for entry in self.transactions.iter() {
+            visit(*entry.key(), *entry.value())?;
+        }
+        Ok(())
+    }
+}
+
+// Size of one entry on disk: flag, kind, state, padding, client, padding, amount
+const SLOT_SIZE: u64 = 16;
+// Slots read at a time when the whole file is scanned
+const SCAN_SLOTS: u64 = 4096;
+// First slot of every history file, it tells a history file left by an earlier run from any other file
+const HISTORY_HEADER: &[u8; SLOT_SIZE as usize] = b"accountant hist1";
+
+/*
+    Keeps the history in a file instead of memory. The file starts with HISTORY_HEADER and every tx
+    id has a fixed slot after it at (tx + 1) * SLOT_SIZE, so an entry is found with a single seek
+    and no index has to be held in memory.
+    Only the numbers of the SCAN_SLOTS sized chunks that hold an entry are kept in memory, so a scan
+    of the whole history skips the ranges of ids that were never used. That is one number per 4096
+    ids when they are dense. The file is sparse, slots of ids that are never used take no space on
+    disk on file systems that support it (ext4, APFS, NTFS).
+    The file is scratch space for one run and it is removed when the store is dropped. A run that
+    crashed leaves it behind, so a history file found at the path is replaced. Any other file at the
+    path is never overwritten.
+ */
+pub struct DiskStore {
+    path: PathBuf,
+    file: Mutex<DiskFile>,
+}
+
+struct DiskFile {
+    file: File,
+    // Slots at or past the end of the file have never been written
+    len: u64,
+    // Chunks of SCAN_SLOTS slots with at least one written slot, for_each only reads these
+    chunks: BTreeSet<u64>,
+}
+
+impl DiskStore {
+    /*
+    Description: Creates the history file. A history file that a run which didn't finish left at
+                 the path is stale and replaced. Any other existing file is refused, the file is
+                 removed again when the store is dropped and that must never take a file that
+                 was there before the run with it.
+    Parameters:
+        path: P  Where the history is kept
+    */
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, EngineError> {
+        let path = path.as_ref();
+        if path.exists() {
+            if !is_history_file(path)? {
+                return Err(EngineError::Io(format!(
+                    "{} already exists and is not a history file, the history needs a new file",
+                    path.display()
+                )));
+            }
+            warn!("Replacing the history file {} left by a run that didn't finish", path.display());
+            fs::remove_file(path)?;
+        }
+        let mut file = OpenOptions::new().read(true).write(true).create_new(true).open(path)?;
+        file.write_all(HISTORY_HEADER)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(DiskFile { file, len: SLOT_SIZE, chunks: BTreeSet::new() }),
+        })
+    }
+
+    fn read_slot(disk: &mut DiskFile, tx: u32) -> Result<Option<LedgerEntry>, EngineError> {
+        let offset = slot_offset(tx);
+        if offset >= disk.len {
+            return Ok(None);
+        }
+        let mut slot = [0u8; SLOT_SIZE as usize];
+        disk.file.seek(SeekFrom::Start(offset))?;
+        disk.file.read_exact(&mut slot)?;
+        decode_slot(tx, &slot)
+    }
+
+    fn write_slot(disk: &mut DiskFile, tx: u32, entry: &LedgerEntry) -> Result<(), EngineError> {
+        let offset = slot_offset(tx);
+        disk.file.seek(SeekFrom::Start(offset))?;
+        disk.file.write_all(&encode_slot(entry))?;
+        disk.len = disk.len.max(offset + SLOT_SIZE);
+        disk.chunks.insert(tx as u64 / SCAN_SLOTS);
+        Ok(())
+    }
+}
+
+impl TransactionStore for DiskStore {
+    fn insert(&self, tx: u32, entry: LedgerEntry) -> Result<bool, EngineError> {
+        let mut disk = self.file.lock().expect("history file lock poisoned");
+        if Self::read_slot(&mut disk, tx)?.is_some() {
+            return Ok(false);
+        }
+        Self::write_slot(&mut disk, tx, &entry)?;
+        Ok(true)
+    }
+
+    fn get(&self, tx: u32) -> Result<Option<LedgerEntry>, EngineError> {
+        let mut disk = self.file.lock().expect("history file lock poisoned");
+        Self::read_slot(&mut disk, tx)
+    }
+
+    fn set_state(&self, tx: u32, state: TxState) -> Result<(), EngineError> {
+        let mut disk = self.file.lock().expect("history file lock poisoned");
+        let mut entry = Self::read_slot(&mut disk, tx)?.ok_or(EngineError::UnknownTx { tx })?;
+        entry.state = state;
+        Self::write_slot(&mut disk, tx, &entry)
+    }
+
+    fn for_each(&self, visit: &mut dyn FnMut(u32, LedgerEntry) -> Result<(), EngineError>) -> Result<(), EngineError> {
+        let mut disk = self.file.lock().expect("history file lock poisoned");
+        let disk = &mut *disk;
+        let mut chunk = vec![0u8; (SLOT_SIZE * SCAN_SLOTS) as usize];
+        for &number in &disk.chunks {
+            let offset = slot_offset((number * SCAN_SLOTS) as u32);
+            let read = (disk.len - offset).min(SLOT_SIZE * SCAN_SLOTS) as usize;
+            disk.file.seek(SeekFrom::Start(offset))?;
+            disk.file.read_exact(&mut chunk[..read])?;
+            for (index, slot) in chunk[..read].chunks_exact(SLOT_SIZE as usize).enumerate() {
+                let tx = (number * SCAN_SLOTS) as u32 + index as u32;
+                let slot = slot.try_into().expect("chunks are slot sized");
+                if let Some(entry) = decode_slot(tx, slot)? {
+                    visit(tx, entry)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn slot_offset(tx: u32) -> u64 {
+    (tx as u64 + 1) * SLOT_SIZE
+}
+
+/*
+Description: Whether the file at path starts with HISTORY_HEADER, a file that is too short isn't
+             a history file
+*/
+fn is_history_file(path: &Path) -> Result<bool, EngineError> {
+    let mut header = [0u8; SLOT_SIZE as usize];
+    match File::open(path)?.read_exact(&mut header) {
+        Ok(()) => Ok(&header == HISTORY_HEADER),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn encode_slot(entry: &LedgerEntry) -> [u8; SLOT_SIZE as usize] {
+    let mut slot = [0u8; SLOT_SIZE as usize];
+    slot[0] = 1;
+    slot[1] = match entry.kind {
+        EntryKind::Deposit => 0,
+        EntryKind::Withdrawal => 1,
+    };
+    slot[2] = match entry.state {
+        TxState::Pending => 0,
+        TxState::Failed => 1,
+        TxState::Processed => 2,
+        TxState::Disputed => 3,
+        TxState::Resolved => 4,
+        TxState::ChargedBack => 5,
+    };
+    slot[4..6].copy_from_slice(&entry.client.to_le_bytes());
+    slot[8..16].copy_from_slice(&entry.amount.raw().to_le_bytes());
+    slot
+}
+
+/*
+Description: Reads an entry back from its slot. A slot that was never written is all zeros and
+             decodes to None.
+*/
+fn decode_slot(tx: u32, slot: &[u8; SLOT_SIZE as usize]) -> Result<Option<LedgerEntry>, EngineError> {
+    if slot[0] == 0 {
+        return Ok(None);
+    }
+    let corrupt = || EngineError::Io(format!("history slot of transaction {} is corrupt", tx));
+    let kind = match slot[1] {
+        0 => EntryKind::Deposit,
+        1 => EntryKind::Withdrawal,
+        _ => return Err(corrupt()),
+    };
+    let state = match slot[2] {
+        0 => TxState::Pending,
+        1 => TxState::Failed,
+        2 => TxState::Processed,
+        3 => TxState::Disputed,
+        4 => TxState::Resolved,
+        5 => TxState::ChargedBack,
+        _ => return Err(corrupt()),
+    };
+    let client = u16::from_le_bytes([slot[4], slot[5]]);
+    let amount = i64::from_le_bytes(slot[8..16].try_into().expect("slot holds 8 amount bytes"));
+    Ok(Some(LedgerEntry {
+        client,
+        kind,
+        amount: Money::from_raw(amount),
+        state,
+    }))
+}
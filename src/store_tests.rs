@@ -0,0 +1,137 @@
+use crate::config::EngineConfig;
+use crate::errors::EngineError;
+use crate::money::Money;
+use crate::store::*;
+use crate::transactions::*;
+use crate::transactions_tests::{balances, generate_rows};
+use test_case::test_case;
+
+fn memory_store() -> (Box<dyn TransactionStore>, Option<tempfile::TempDir>) {
+    (Box::new(InMemoryStore::new()), None)
+}
+
+fn disk_store() -> (Box<dyn TransactionStore>, Option<tempfile::TempDir>) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = DiskStore::create(dir.path().join("history.bin")).unwrap();
+    (Box::new(store), Some(dir))
+}
+
+fn entry(client: u16, kind: EntryKind, amount: &str) -> LedgerEntry {
+    LedgerEntry {
+        client,
+        kind,
+        amount: amount.parse::<Money>().unwrap(),
+        state: TxState::Processed,
+    }
+}
+
+/*
+    Both stores have to behave the same:
+    i. An id can only be inserted once, the first entry is kept
+    ii. Ids that were never inserted are not found, also ids far past the last one written
+    iii. State changes are kept, negative amounts and the largest tx id round trip
+ */
+#[test_case(memory_store)]
+#[test_case(disk_store)]
+fn test_transaction_store(store: fn() -> (Box<dyn TransactionStore>, Option<tempfile::TempDir>)) {
+    let (store, _dir) = store();
+    assert_eq!(store.insert(3, entry(1, EntryKind::Deposit, "10.5")), Ok(true));
+    assert_eq!(store.insert(3, entry(2, EntryKind::Withdrawal, "1")), Ok(false));
+    assert_eq!(store.get(3), Ok(Some(entry(1, EntryKind::Deposit, "10.5"))));
+
+    assert_eq!(store.get(2), Ok(None));
+    assert_eq!(store.get(1_000_000), Ok(None));
+    assert_eq!(store.set_state(2, TxState::Disputed), Err(EngineError::UnknownTx { tx: 2 }));
+
+    store.set_state(3, TxState::Disputed).unwrap();
+    assert_eq!(store.get(3).unwrap().unwrap().state, TxState::Disputed);
+
+    assert_eq!(store.insert(u32::MAX, entry(7, EntryKind::Withdrawal, "-0.0001")), Ok(true));
+    assert_eq!(store.get(u32::MAX), Ok(Some(entry(7, EntryKind::Withdrawal, "-0.0001"))));
+}
+
+#[test]
+fn test_disk_store_removes_its_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.bin");
+    let store = DiskStore::create(&path).unwrap();
+    store.insert(1, entry(1, EntryKind::Deposit, "1")).unwrap();
+    assert!(path.exists());
+    drop(store);
+    assert!(!path.exists());
+}
+
+/*
+    A file at the history path that isn't a history file is refused and left as it is, also when
+    it is shorter than the header
+ */
+#[test_case("keep me, this is not a history file")]
+#[test_case("keep me")]
+fn test_disk_store_refuses_existing_file(content: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.bin");
+    std::fs::write(&path, content).unwrap();
+    assert!(matches!(DiskStore::create(&path), Err(EngineError::Io(_))));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+}
+
+/*
+    A run that crashed never drops its store and leaves the history file behind. The next run
+    replaces it and starts from an empty history.
+ */
+#[test]
+fn test_disk_store_replaces_stale_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.bin");
+    let crashed = DiskStore::create(&path).unwrap();
+    crashed.insert(1, entry(1, EntryKind::Deposit, "1")).unwrap();
+    std::mem::forget(crashed);
+    assert!(path.exists());
+
+    let store = DiskStore::create(&path).unwrap();
+    assert_eq!(store.get(1), Ok(None));
+    store.insert(2, entry(1, EntryKind::Deposit, "2")).unwrap();
+    drop(store);
+    assert!(!path.exists());
+}
+
+/*
+    for_each visits every stored entry once. The ids are far apart, the disk store only reads the
+    chunks that hold them and not the slots of every id in between.
+ */
+#[test_case(memory_store)]
+#[test_case(disk_store)]
+fn test_for_each_visits_sparse_ids(store: fn() -> (Box<dyn TransactionStore>, Option<tempfile::TempDir>)) {
+    let (store, _dir) = store();
+    let ids = [1, 4_095, 4_096, 2_000_000_000, u32::MAX];
+    for tx in ids {
+        store.insert(tx, entry(1, EntryKind::Deposit, "1")).unwrap();
+    }
+    let mut visited = Vec::new();
+    store.for_each(&mut |tx, _| {
+        visited.push(tx);
+        Ok(())
+    }).unwrap();
+    visited.sort_unstable();
+    assert_eq!(visited, ids);
+}
+
+/*
+    A run with the history on disk gives the same balances and rejections as one in memory, also
+    when the rows are sharded between threads.
+ */
+#[test_case(1)]
+#[test_case(4)]
+fn test_disk_store_matches_memory(threads: usize) {
+    let input = generate_rows(5_000, 20, 3);
+    let memory = PaymentsEngine::new();
+    let memory_summary = memory.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    let (store, _dir) = disk_store();
+    let mut disk = PaymentsEngine::with_config(EngineConfig { threads, ..EngineConfig::default() });
+    disk.set_transaction_store(store);
+    let disk_summary = disk.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(disk_summary, memory_summary);
+    assert_eq!(balances(&disk), balances(&memory));
+}
@@ -1,281 +1,1435 @@
-use std::error::Error;
 use std::fs::File;
-use serde::{Deserialize};
+use std::io::Read;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use csv::{ReaderBuilder};
 use crate::client::{Client};
+use crate::money::{Money, MoneyError};
+use crate::errors::EngineError;
+use crate::config::{AmountRules, DuplicatePolicy, EngineConfig, PrecisionPolicy};
+use crate::journal::{InputPosition, Journal, JournaledRow};
+use crate::adjustments::{Adjustment, AdjustmentsWriter};
+use crate::rejects::{encode_record, Rejection, RejectsWriter};
+use crate::store::{InMemoryStore, TransactionStore};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 use dashmap::DashMap;
-use std::sync::LazyLock;
+use dashmap::mapref::one::RefMut;
+use std::mem;
+use std::sync::{mpsc, Mutex};
+use std::thread;
 use log::{error, info, warn};
 
 /*
-    TODO: Create a constructor, getters, and setters for Transactions to block off direct access to
-          Transaction fields. For general usage it was not necessary, but the unit tests need
-          to be access without the presence of a csv file
+    One row of the input. Rows are read from csv by the engine, code that embeds the engine builds
+    them with Transaction::builder:
+        Transaction::builder(TransactionKind::Deposit, 1, 1).amount(amount).build()
  */
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Transaction {
     #[serde(rename = "type")]
-    pub(crate) transaction_type: String,
+    pub(crate) kind: TransactionKind,
     pub(crate) client: u16,
     pub(crate) tx: u32,
-    pub(crate) amount: Option<f32>,
+    // Left out or empty for dispute, resolve, chargeback, lock and unlock rows
+    #[serde(default)]
+    pub(crate) amount: Option<Money>,
+    // Why an adjust row was made, only admin rows carry it
+    #[serde(default)]
+    pub(crate) reason: Option<String>,
+}
+
+impl Transaction {
+    pub fn builder(kind: TransactionKind, client: u16, tx: u32) -> TransactionBuilder {
+        TransactionBuilder {
+            transaction: Transaction {
+                kind,
+                client,
+                tx,
+                amount: None,
+                reason: None,
+            },
+        }
+    }
+
+    pub fn kind(&self) -> TransactionKind {
+        self.kind
+    }
+
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn tx(&self) -> u32 {
+        self.tx
+    }
+
+    pub fn amount(&self) -> Option<Money> {
+        self.amount
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /*
+    Description: The transaction as a row with the columns of TRANSACTION_COLUMNS, in that order.
+                 This is how rows are journaled, whatever the column order of the input was.
+    */
+    pub(crate) fn to_record(&self) -> csv::StringRecord {
+        csv::StringRecord::from(vec![
+            self.kind.to_string(),
+            self.client.to_string(),
+            self.tx.to_string(),
+            self.amount.map_or_else(String::new, |amount| amount.to_string()),
+            self.reason.clone().unwrap_or_default(),
+        ])
+    }
 }
 
 /*
-    Using a Dashmap(Rust Hashmap with built-in handling of concurrency) to store all transactions
-    from csv file. If specifically using hashmap was required I would mutex lock each transaction
-    and client as they were being modified to prevent race conditions when multi-threading.
+    Builds a Transaction. The amount is left out for dispute, resolve, chargeback, lock and
+    unlock, the reason is only read by adjust. Nothing is checked here, the engine checks a
+    transaction when it is applied and rejects it the same way it rejects a row from a file.
  */
+#[derive(Debug, Clone)]
+pub struct TransactionBuilder {
+    transaction: Transaction,
+}
 
-static TRANSACTIONS_MAP: LazyLock<DashMap<u32, Transaction>> = LazyLock::new(|| DashMap::new());
+impl TransactionBuilder {
+    pub fn amount(mut self, amount: Money) -> Self {
+        self.transaction.amount = Some(amount);
+        self
+    }
 
-pub fn process_transactions(input_file: &str) -> Result<DashMap<u16, Client>, Box<dyn Error>> {
-    // Client Map keeps a copy of all client data in a map for future reference
-    let client_map: DashMap<u16, Client> = DashMap::new();
-    let transaction_file = File::open(input_file)?;
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.transaction.reason = Some(reason.into());
+        self
+    }
 
-    let mut transaction_reader = ReaderBuilder::new()
-        .flexible(true)
-        .trim(csv::Trim::All)
-        .has_headers(true)
-        .from_reader(transaction_file);
+    pub fn build(self) -> Transaction {
+        self.transaction
+    }
+}
 
-    for row in transaction_reader.records() {
-        match row {
-            Ok(record) => {
-                if let Some(transaction) = parse_transaction(&record) {
-                    let mut client_entry = client_map
-                        .entry(transaction.client)
-                        .or_insert_with(|| {
-                            info!("Creating new client: {}", transaction.client);
-                            Client::new(transaction.client)
-                        });
-
-                    // Convert the transaction type to lowercase for case-insensitive matching
-                    match transaction.transaction_type.to_lowercase().as_str() {
-                        "deposit" => process_deposit(&mut client_entry, &transaction),
-                        "withdrawal" => process_withdrawal(&mut client_entry, &transaction),
-                        "dispute" => process_dispute(&mut client_entry, &transaction),
-                        "resolve" => process_resolve(&mut client_entry, &transaction),
-                        "chargeback" => process_chargeback(&mut client_entry, &transaction),
-
-                        _ => println!(
-                            "Unsupported transaction type: {:?} for client {:?}",
-                            transaction.transaction_type, transaction.client
-                        ),
-                    }
+/*
+    Every kind of row the engine understands, parsed from the type column when the row is read.
+    Lock, unlock and adjust are admin rows that are only accepted when the engine allows them.
+    Names are matched without regard to case or surrounding spaces, "Deposit" and " DEPOSIT" are
+    both a deposit. Any other name fails to parse with unsupported_type, the row never reaches a
+    client.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Lock,
+    Unlock,
+    Adjust,
+}
 
-                    TRANSACTIONS_MAP.insert(transaction.tx, transaction);
-                } else {
-                    eprintln!("Skipping invalid transaction: {:?}", record);
-                }
-            }
-            Err(err) => {
-                eprintln!(
-                    "Error reading transactions from the CSV file: {}",
-                    err
-                );
-            }
+impl FromStr for TransactionKind {
+    type Err = EngineError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "deposit" => Ok(TransactionKind::Deposit),
+            "withdrawal" => Ok(TransactionKind::Withdrawal),
+            "dispute" => Ok(TransactionKind::Dispute),
+            "resolve" => Ok(TransactionKind::Resolve),
+            "chargeback" => Ok(TransactionKind::Chargeback),
+            "lock" => Ok(TransactionKind::Lock),
+            "unlock" => Ok(TransactionKind::Unlock),
+            "adjust" => Ok(TransactionKind::Adjust),
+            _ => Err(EngineError::UnsupportedType { transaction_type: value.to_string() }),
+        }
+    }
+}
+
+impl TransactionKind {
+    /*
+    Description: The kind of history entry a row of this kind creates, None for rows that only
+                 reference a tx or are admin rows
+    */
+    pub fn entry_kind(self) -> Option<EntryKind> {
+        match self {
+            TransactionKind::Deposit => Some(EntryKind::Deposit),
+            TransactionKind::Withdrawal => Some(EntryKind::Withdrawal),
+            _ => None,
         }
     }
-    Ok(client_map)
 }
 
+impl Serialize for TransactionKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
 
-// Disputes, Chargebacks, Resolves may not have an amount provided.
-// Using .flexible() for the csv crate does not seem to allow for varying rows
-// so extracting each value bit by bit from the row seems to be the best way to handle this at the
-// moment. Will look to improve in future iteration.
-fn parse_transaction(record: &csv::StringRecord) -> Option<Transaction> {
-    let transaction_type = record.get(0)?.to_string();
-    let client = record.get(1)?.parse::<u16>().ok()?;
-    let tx = record.get(2)?.parse::<u32>().ok()?;
+struct TransactionKindVisitor;
 
-    let amount = if transaction_type == "dispute" && record.get(3).is_none() {
-        None
-    } else {
-        record.get(3).and_then(|s| s.parse::<f32>().ok())
-    };
+impl Visitor<'_> for TransactionKindVisitor {
+    type Value = TransactionKind;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a transaction type such as deposit or withdrawal")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<TransactionKind, E> {
+        value.parse().map_err(E::custom)
+    }
+}
 
-    Some(Transaction {
-        transaction_type,
-        client,
-        tx,
-        amount,
-    })
+impl<'de> Deserialize<'de> for TransactionKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TransactionKindVisitor)
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransactionKind::Deposit => "deposit",
+            TransactionKind::Withdrawal => "withdrawal",
+            TransactionKind::Dispute => "dispute",
+            TransactionKind::Resolve => "resolve",
+            TransactionKind::Chargeback => "chargeback",
+            TransactionKind::Lock => "lock",
+            TransactionKind::Unlock => "unlock",
+            TransactionKind::Adjust => "adjust",
+        };
+        f.write_str(name)
+    }
 }
 
 /*
-Description: Begins the deposit process, first checking if the account is locked. Transactions
-             performed on a locked account will be ignored. Only positive value will be accepted.
-Parameters:
-    client_entry: &mut Mutable reference to the instance of the client
-    transaction: &Transaction  Reference to Transaction struct
-*/
-pub(crate) fn process_deposit(client_entry: &mut Client, transaction: &Transaction) {
-    // If account is locked skip the transaction
-    if client_entry.is_account_locked(transaction.tx) {
-        return;
-    }
-    // Check if the transaction has a valid amount
-    if let Some(amount) = transaction.amount {
-        if amount > 0.0 {
-            client_entry.deposit(Some(amount));
-            info!(
-                "Deposit of ${:.2} successful for client {}. New available balance: ${:.2}",
-                amount, client_entry.id(), client_entry.available()
-            );
-        } else {
-            warn!("Cannot deposit a zero or negative amount of money");
+    The kinds of transaction that move money and can therefore be disputed. Dispute, resolve and
+    chargeback rows only reference one of these by tx id.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    Deposit,
+    Withdrawal,
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EntryKind::Deposit => "deposit",
+            EntryKind::Withdrawal => "withdrawal",
+        };
+        f.write_str(name)
+    }
+}
+
+/*
+    A deposit or withdrawal kept in the engine's history so later disputes can find the client
+    and amount of the tx they reference. Dispute, resolve and chargeback rows are never stored.
+    Outside the crate an entry can only be read, entries are made and changed by the engine.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub(crate) client: u16,
+    pub(crate) kind: EntryKind,
+    pub(crate) amount: Money,
+    pub(crate) state: TxState,
+}
+
+/*
+    Where a stored transaction is in the dispute lifecycle. The only legal transitions are
+        Processed -> Disputed -> Resolved
+                              -> ChargedBack
+    Resolved and ChargedBack are final, a tx can only be disputed once.
+    A deposit or withdrawal claims its tx id as soon as it is read, in input order, and is Pending
+    until it has been applied. It then becomes Processed, or Failed when it was rejected. Failed
+    entries only reserve the id, they can't be disputed.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxState {
+    Pending,
+    Failed,
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl LedgerEntry {
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    pub fn amount(&self) -> Money {
+        self.amount
+    }
+
+    pub fn state(&self) -> TxState {
+        self.state
+    }
+
+    /*
+    Description: Checks that the entry may move to the given state. All transition rules for the
+                 dispute lifecycle live here, the caller only commits the new state once the
+                 client's balances were updated.
+    Parameters:
+        tx: u32  Id of this entry, used in the error
+        to: TxState  The state the entry should move to
+    */
+    pub(crate) fn check_transition(&self, tx: u32, to: TxState) -> Result<(), EngineError> {
+        match (self.state, to) {
+            (TxState::Processed, TxState::Disputed)
+            | (TxState::Disputed, TxState::Resolved)
+            | (TxState::Disputed, TxState::ChargedBack) => Ok(()),
+            (TxState::Disputed, TxState::Disputed) => Err(EngineError::AlreadyDisputed { tx }),
+            (TxState::Processed, TxState::Resolved | TxState::ChargedBack) => {
+                Err(EngineError::NotDisputed { tx })
+            }
+            (from, to) => Err(EngineError::IllegalTransition { tx, from, to }),
         }
-    } else {
-        warn!("Invalid deposit amount for client {}", client_entry.id());
     }
 }
 
+// Rows handed to a worker at a time, and how many batches may wait in a worker's queue
+const WORKER_BATCH_SIZE: usize = 512;
+const WORKER_QUEUE_BATCHES: usize = 16;
+
 /*
-Description: Begins the withdrawal process, checks for locked account. Only positive values will
-             be accepted.
-Parameters:
-    client_entry: &mut Mutable reference to the instance of the client
-    transaction: &Transaction  Reference to Transaction struct
-*/
-pub(crate) fn process_withdrawal(client_entry: &mut Client, transaction: &Transaction) {
-    if client_entry.is_account_locked(transaction.tx) {
-        return;
-    }
-    if let Some(amount) = transaction.amount {
-        if amount > 0.0 {
-            let client_id = client_entry.id();
-            match client_entry.withdraw(Some(amount)) {
-                Ok(_) => info!(
-                    "Withdrawal of ${:.4} successful for client {}. New available balance: ${:.4}",
-                    amount, client_id, client_entry.available()
-                ),
-                Err(error) => error!(
-                    "Sorry Santa, you've exceeded your limit for this client {}: {}",
-                    client_id, error
-                ),
+    One input of a run. index is the place of the input in the run and orders its rows in the
+    journal, name is the file name rejected rows are reported with, None when the input isn't a
+    file. header holds the column names the rows are read with.
+ */
+struct Source {
+    index: usize,
+    name: Option<String>,
+    header: csv::StringRecord,
+}
+
+impl Source {
+    /*
+    Description: The source of rows replayed from the journal for one input of the run. The input is
+                 named after the journal's record of it, stdin has no name. The header is the one
+                 the journal recorded for the input, the columns the rows were read in. Replayed
+                 rows that were parsed are in the usual columns and don't need it.
+    */
+    fn journal(index: usize, journal: &Journal) -> Self {
+        let input = journal.inputs().get(index);
+        let name = input.map(|input| input.path.clone()).filter(|path| path != "-");
+        let header = input
+            .and_then(|input| input.header.as_deref())
+            .and_then(|header| ReaderBuilder::new().has_headers(false).from_reader(header.as_bytes()).records().next())
+            .and_then(|header| read_header(&header.ok()?).ok())
+            .unwrap_or_else(|| csv::StringRecord::from(TRANSACTION_COLUMNS.to_vec()));
+        Self { index, name, header }
+    }
+
+    fn position(&self, line: u64) -> InputPosition {
+        InputPosition { input: self.index, line }
+    }
+
+    /*
+    Description: Names a row of this input for the log, "row 3 of day1.csv"
+    */
+    fn describe(&self, line: u64) -> String {
+        match &self.name {
+            Some(name) => format!("row {} of {}", line, name),
+            None => format!("row {}", line),
+        }
+    }
+}
+
+/*
+    A row after it was read and admitted, on its way to be applied. The raw record is kept for the
+    rejects report. admission holds the error of a row that failed the checks made when it was
+    read, it is raised when the row is applied so the client is still created in order.
+    adjustment notes an amount that was rounded or truncated by the precision policy.
+ */
+struct AdmittedRow {
+    line: u64,
+    record: Option<csv::StringRecord>,
+    transaction: Result<Transaction, EngineError>,
+    admission: Result<(), EngineError>,
+    adjustment: Option<AmountAdjustment>,
+}
+
+impl AdmittedRow {
+    /*
+    Description: The rejects report entry of this row. tx and client come from the parsed
+                 transaction when there is one, a replayed row's record is in the input's columns
+                 and not in the columns of its source.
+    */
+    fn rejection(&self, source: &Source, reason: EngineError) -> Rejection {
+        let mut rejection = Rejection::from_record(source.name.as_deref(), self.line, self.record.as_ref(), &source.header, reason);
+        if let Ok(transaction) = &self.transaction {
+            rejection.tx = Some(transaction.tx);
+            rejection.client = Some(transaction.client);
+        }
+        rejection
+    }
+
+    fn apply(&self, engine: &PaymentsEngine) -> Result<(), EngineError> {
+        let transaction = self.transaction.as_ref().map_err(EngineError::clone)?;
+        match &self.admission {
+            Ok(()) => engine.process_transaction(transaction),
+            Err(err) => {
+                engine.client_entry(transaction.client);
+                Err(err.clone())
             }
-        } else {
-            warn!("Cannot withdraw a non-positive amount");
         }
-    } else {
-        warn!("Invalid withdrawal amount for client {}", client_entry.id());
     }
 }
 
 /*
-Description: Begins the dispute process, checks for locked account. Proceeds to check if transaction
-             in the dispute exists in the transaction_map.
-Parameters:
-    client_entry: &mut Mutable reference to the instance of the client
-    transaction: &Transaction  Reference to Transaction struct
-*/
-pub(crate) fn process_dispute(client_entry: &mut Client, transaction: &Transaction) {
-    if client_entry.is_account_locked(transaction.tx) {
-        return;
-    }
-    if let Some(disputed_transaction) = TRANSACTIONS_MAP.get(&transaction.tx) {
-        if let Some(disputed_amount) = disputed_transaction.value().amount {
-            match client_entry.dispute(transaction.tx, Some(disputed_amount)) {
-                Ok(_) => {
-                    info!(
-                        "Dispute successful for client {}: ${:.4} moved to Held, Available balance is now ${:.4}.",
-                        transaction.client, disputed_amount, client_entry.available()
-                    );
+    Totals for a single run over an input. Rejected rows are counted per reason code so the
+    caller can see at a glance why rows were not applied.
+ */
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RunSummary {
+    pub rows_read: usize,
+    pub rows_rejected: usize,
+    pub rejections: BTreeMap<&'static str, usize>,
+    // Rows applied with an amount that was rounded or truncated to the allowed decimal places
+    pub amounts_adjusted: usize,
+}
+
+impl RunSummary {
+    pub fn record_rejection(&mut self, error: &EngineError) {
+        self.rows_rejected += 1;
+        *self.rejections.entry(error.code()).or_insert(0) += 1;
+    }
+
+    /*
+    Description: Adds the counts of another summary, used to combine the summaries of the workers
+    */
+    pub fn merge(&mut self, other: RunSummary) {
+        self.rows_read += other.rows_read;
+        self.rows_rejected += other.rows_rejected;
+        self.amounts_adjusted += other.amounts_adjusted;
+        for (code, count) in other.rejections {
+            *self.rejections.entry(code).or_insert(0) += count;
+        }
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rows read, {} rejected", self.rows_read, self.rows_rejected)?;
+        for (code, count) in &self.rejections {
+            write!(f, ", {}: {}", code, count)?;
+        }
+        if self.amounts_adjusted > 0 {
+            write!(f, ", {} amounts adjusted", self.amounts_adjusted)?;
+        }
+        Ok(())
+    }
+}
+
+/*
+    The engine owns everything a ledger needs: the clients and the history of transactions that
+    disputes look up. Using a Dashmap(Rust Hashmap with built-in handling of concurrency) for the
+    clients. If specifically using hashmap was required I would mutex lock each transaction and
+    client as they were being modified to prevent race conditions when multi-threading. The history
+    lives in a TransactionStore, in memory unless a disk backed store is set. Each engine is
+    isolated, several ledgers can be run side by side in one process.
+ */
+pub struct PaymentsEngine {
+    config: EngineConfig,
+    // Client Map keeps a copy of all client data in a map for future reference
+    clients: DashMap<u16, Client>,
+    // Deposits and withdrawals only, keyed by tx id
+    transactions: Box<dyn TransactionStore>,
+    // Optional report of every row that was not applied
+    rejects: Mutex<Option<RejectsWriter>>,
+    // Optional report of every amount the precision policy rounded or truncated
+    adjustments: Mutex<Option<AdjustmentsWriter>>,
+    // Optional write-ahead journal of every row read
+    journal: Mutex<Option<Journal>>,
+    // Rows up to this position were recovered from the journal and are skipped
+    resume_after: InputPosition,
+}
+
+impl Default for PaymentsEngine {
+    fn default() -> Self {
+        Self {
+            config: EngineConfig::default(),
+            clients: DashMap::new(),
+            transactions: Box::new(InMemoryStore::new()),
+            rejects: Mutex::new(None),
+            adjustments: Mutex::new(None),
+            journal: Mutex::new(None),
+            resume_after: InputPosition::default(),
+        }
+    }
+}
+
+impl PaymentsEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /*
+    Description: Sends every row that is not applied to the given rejects report from now on
+    */
+    pub fn set_rejects_writer(&mut self, writer: RejectsWriter) {
+        self.rejects = Mutex::new(Some(writer));
+    }
+
+    /*
+    Description: Sends every row whose amount was rounded or truncated to the given adjustments
+                 report from now on
+    */
+    pub fn set_adjustments_writer(&mut self, writer: AdjustmentsWriter) {
+        self.adjustments = Mutex::new(Some(writer));
+    }
+
+    /*
+    Description: Keeps the transaction history in the given store. Set it before processing, the
+                 history already in the old store is not carried over.
+    */
+    pub fn set_transaction_store(&mut self, store: Box<dyn TransactionStore>) {
+        self.transactions = store;
+    }
+
+    /*
+    Description: Replays the rows of a journal left by an earlier run of the same input and then
+                 journals every row read from now on. The engine has to be in the state it was in
+                 when the journal was started, fresh or loaded from the same snapshot. Rows up to
+                 the last journaled line are skipped when the input is processed, so a run that
+                 was killed continues where it stopped without applying anything twice.
+                 Replayed rows that are rejected are reported with their file and the row as it
+                 was read. Returns the summary of the replayed rows.
+    Parameters:
+        journal: Journal  The opened journal
+    */
+    pub fn recover_from_journal(&mut self, mut journal: Journal) -> Result<RunSummary, EngineError> {
+        let mut summary = RunSummary::default();
+        let sources: Vec<Source> = (0..journal.inputs().len().max(1)).map(|index| Source::journal(index, &journal)).collect();
+        let columns = csv::StringRecord::from(TRANSACTION_COLUMNS.to_vec());
+        journal.replay(|position, row| {
+            summary.rows_read += 1;
+            let source = sources.get(position.input).ok_or_else(|| EngineError::JournalMismatch {
+                reason: format!("row {} is from an input it doesn't record", position),
+            })?;
+            let row = match row {
+                JournaledRow::Parsed { record, raw } => {
+                    let mut row = self.admit_parsed(self.parse_record(record, &columns, position.line));
+                    // The journaled amount is the adjusted one, the row as it was read still has the
+                    // amount the precision policy changed
+                    row.adjustment = parse_transaction(&raw, &source.header, position.line, &self.config.amounts)
+                        .ok()
+                        .and_then(|(_, adjustment)| adjustment);
+                    row.record = Some(raw);
+                    self.note_adjustment(&mut summary, &row, source)?;
+                    row
                 }
+                JournaledRow::Rejected { raw, reason } => AdmittedRow {
+                    line: position.line,
+                    record: raw,
+                    transaction: Err(reason),
+                    admission: Ok(()),
+                    adjustment: None,
+                },
+            };
+            match row.apply(self) {
+                Ok(()) => Ok(()),
+                Err(err) => self.reject(&mut summary, &row, source, err),
+            }
+        })?;
+        if summary.rows_read > 0 {
+            info!("Recovered {} rows from the journal up to row {}: {}", summary.rows_read, journal.last_position(), summary);
+        }
+        self.resume_after = journal.last_position();
+        self.journal = Mutex::new(Some(journal));
+        Ok(summary)
+    }
+
+    pub(crate) fn transaction_store(&self) -> &dyn TransactionStore {
+        self.transactions.as_ref()
+    }
+
+    /*
+    Description: A copy of the stored deposit or withdrawal with this tx id, None when there is none
+    */
+    pub fn transaction(&self, tx: u32) -> Result<Option<LedgerEntry>, EngineError> {
+        self.transactions.get(tx)
+    }
+
+    pub(crate) fn clients(&self) -> &DashMap<u16, Client> {
+        &self.clients
+    }
+
+    /*
+    Description: A copy of one client's account, None when the client has not been seen
+    */
+    pub fn client(&self, id: u16) -> Option<Client> {
+        self.clients.get(&id).map(|client| client.clone())
+    }
+
+    /*
+    Description: A copy of every client's account, sorted by client id
+    */
+    pub fn client_list(&self) -> Vec<Client> {
+        let mut clients: Vec<Client> = self.clients.iter().map(|client| client.clone()).collect();
+        clients.sort_by_key(Client::id);
+        clients
+    }
+
+    /*
+    Description: Applies one transaction built by the caller, with the same checks as a row read
+                 from the input. A rejected transaction is returned as the error and is neither
+                 journaled nor written to the rejects report, the caller decides what to do with it.
+    Parameters:
+        transaction: &Transaction  The transaction to apply
+    */
+    pub fn apply(&self, transaction: &Transaction) -> Result<(), EngineError> {
+        match self.admit(transaction) {
+            Ok(()) => self.process_transaction(transaction),
+            Err(err) => {
+                self.client_entry(transaction.client);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn process_transactions(&self, input_file: &str) -> Result<RunSummary, EngineError> {
+        let transaction_file = File::open(input_file)?;
+        self.process_source(0, Some(input_file), transaction_file)
+    }
+
+    /*
+    Description: Processes several input files one after the other against the same clients and
+                 history, so a dispute in a later file can reference a transaction from an earlier
+                 one. Rejected rows are reported with the name of their file. A file that can't be
+                 opened or has no usable header stops the run, the files before it stay applied.
+                 Returns the summary of all the files together.
+    Parameters:
+        input_files: &[S]  Paths of the input files, in the order they are processed
+    */
+    pub fn process_files<S: AsRef<str>>(&self, input_files: &[S]) -> Result<RunSummary, EngineError> {
+        let mut summary = RunSummary::default();
+        for (index, input_file) in input_files.iter().enumerate() {
+            let input_file = input_file.as_ref();
+            let processed = File::open(input_file)
+                .map_err(EngineError::from)
+                .and_then(|transaction_file| self.process_source(index, Some(input_file), transaction_file));
+            match processed {
+                Ok(file_summary) => summary.merge(file_summary),
                 Err(err) => {
-                    error!(
-                        "Error processing dispute for client {}: {}",
-                        transaction.client, err
-                    );
+                    error!("Stopped at {}: {}", input_file, err);
+                    return Err(err);
                 }
             }
+        }
+        Ok(summary)
+    }
+
+    /*
+    Description: Processes transactions from any byte source (a file, stdin, a socket or an in-memory
+                 slice). Rows are streamed through the csv reader one at a time, so the input is never
+                 fully loaded into memory. Rows that fail are logged, counted and written to the
+                 rejects report, they don't stop the run. Returns a summary of the rows read and
+                 rejected.
+                 With more than one thread configured the rows are sharded by client between worker
+                 threads, see process_rows_parallel.
+    Parameters:
+        input: R  Any std::io::Read source containing the transaction csv with a header row
+    */
+    pub fn process_transactions_from_reader<R: Read>(&self, input: R) -> Result<RunSummary, EngineError> {
+        self.process_source(0, None, input)
+    }
+
+    /*
+    Description: Processes one input of a run, see process_transactions_from_reader
+    Parameters:
+        index: usize  Place of the input in the run, it orders the rows in the journal
+        name: Option<&str>  File name of the input for the rejects report and the log
+        input: R  The transaction csv with a header row
+    */
+    fn process_source<R: Read>(&self, index: usize, name: Option<&str>, input: R) -> Result<RunSummary, EngineError> {
+        let mut transaction_reader = ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .has_headers(true)
+            .from_reader(input);
+        let source = Source {
+            index,
+            name: name.map(str::to_string),
+            header: read_header(transaction_reader.headers()?)?,
+        };
+
+        let summary = if self.config.threads > 1 {
+            self.process_rows_parallel(transaction_reader, &source, self.config.threads)?
         } else {
-            warn!(
-                "Error: Transaction ID {} has no amount to dispute.",
-                transaction.tx
-            );
+            self.process_rows(transaction_reader, &source)?
+        };
+        if let Some(writer) = self.rejects.lock().expect("rejects writer lock poisoned").as_mut() {
+            writer.flush()?;
         }
-    } else {
-        warn!(
-            "Error: Transaction ID {} not found for dispute.",
-            transaction.tx
-        );
+        if let Some(writer) = self.adjustments.lock().expect("adjustments writer lock poisoned").as_mut() {
+            writer.flush()?;
+        }
+        if let Some(journal) = self.journal.lock().expect("journal lock poisoned").as_mut() {
+            journal.flush()?;
+        }
+        match &source.name {
+            Some(name) => info!("Run summary of {}: {}", name, summary),
+            None => info!("Run summary: {}", summary),
+        }
+        Ok(summary)
     }
-}
 
-/*
-Description: Used on transactions already under dispute, checks for locked account. Proceeds to
-             check if transaction id is in the clients disputed_transaction hashmap.
-Parameters:
-    client_entry: &mut Mutable reference to the instance of the client
-    transaction: &Transaction  Reference to Transaction struct
-*/
-pub(crate) fn process_resolve(client_entry: &mut Client, transaction: &Transaction) {
-    if client_entry.locked() {
-        log::warn!(
-            "Skipping {} for transaction {}: Account {} is locked.",
-            transaction.transaction_type,
-            transaction.tx,
-            client_entry.id()
-        );
-        return;
+    /*
+    Description: Applies every row on the calling thread, in input order
+    */
+    fn process_rows<R: Read>(&self, mut transaction_reader: csv::Reader<R>, source: &Source) -> Result<RunSummary, EngineError> {
+        let mut summary = RunSummary::default();
+        for row in transaction_reader.records() {
+            let Some(line) = self.line_to_read(&row, source) else {
+                continue;
+            };
+            summary.rows_read += 1;
+            let row = self.admit_row(row, line, source);
+            self.journal_row(&row, source)?;
+            self.note_adjustment(&mut summary, &row, source)?;
+            if let Err(err) = row.apply(self) {
+                self.reject(&mut summary, &row, source, err)?;
+            }
+        }
+        Ok(summary)
     }
-    if client_entry.disputed_transactions().contains_key(&transaction.tx) {
-        match client_entry.resolve(transaction.tx) {
-            Ok(_) => {
-                info!(
-                    "Transaction {} was resolved {}. Held funds are now available.",
+
+    /*
+    Description: Runs the rows through a pipeline of threads:
+                 - the calling thread reads the csv and hands the raw rows in batches to the parser
+                   threads in turn
+                 - the parser threads parse the rows and check their amounts
+                 - the admission thread takes the parsed batches back in the same turn, so in input
+                   order, admits and journals every row and hands it to one worker per shard,
+                   picked by client id
+                 - the workers apply the rows of their shard
+                 A client's rows always go to the same worker and are queued in input order, so
+                 each client sees its transactions in the order they were read while different
+                 clients are processed in parallel.
+                 Rows are sent in batches to keep the channel overhead low. The queues are bounded
+                 so a slow stage holds back the reader instead of buffering the whole input.
+                 The balances end up identical to a sequential run because clients don't share
+                 anything but the tx id history, and every check against the history that involves
+                 another client is made by the admission thread in input order before a row
+                 reaches a worker. The order of the rows in the rejects report is not guaranteed.
+    Parameters:
+        transaction_reader: csv::Reader<R>  The input positioned after the header
+        source: &Source  The input with the column names read by read_header
+        threads: usize  Number of parser threads and of workers
+    */
+    fn process_rows_parallel<R: Read>(&self, mut transaction_reader: csv::Reader<R>, source: &Source, threads: usize) -> Result<RunSummary, EngineError> {
+        thread::scope(|scope| {
+            let mut queues = Vec::with_capacity(threads);
+            let mut workers = Vec::with_capacity(threads);
+            for _ in 0..threads {
+                let (sender, receiver) = mpsc::sync_channel::<Vec<AdmittedRow>>(WORKER_QUEUE_BATCHES);
+                queues.push(sender);
+                workers.push(scope.spawn(move || self.run_worker(receiver, source)));
+            }
+
+            let mut parser_queues = Vec::with_capacity(threads);
+            let mut parsed_queues = Vec::with_capacity(threads);
+            for _ in 0..threads {
+                let (sender, receiver) = mpsc::sync_channel::<Vec<(u64, csv::Result<csv::StringRecord>)>>(WORKER_QUEUE_BATCHES);
+                let (parsed_sender, parsed_receiver) = mpsc::sync_channel::<Vec<AdmittedRow>>(WORKER_QUEUE_BATCHES);
+                parser_queues.push(sender);
+                parsed_queues.push(parsed_receiver);
+                scope.spawn(move || {
+                    for batch in receiver {
+                        let parsed = batch.into_iter().map(|(line, row)| self.parse_row(row, line, source)).collect();
+                        if parsed_sender.send(parsed).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            let admission = scope.spawn(move || self.admit_in_order(parsed_queues, queues, source));
+
+            let mut batch = Vec::with_capacity(WORKER_BATCH_SIZE);
+            let mut parser = 0;
+            for row in transaction_reader.records() {
+                let Some(line) = self.line_to_read(&row, source) else {
+                    continue;
+                };
+                batch.push((line, row));
+                // A failed send means admission stopped early, its error is picked up on join
+                if batch.len() == WORKER_BATCH_SIZE {
+                    if parser_queues[parser].send(mem::replace(&mut batch, Vec::with_capacity(WORKER_BATCH_SIZE))).is_err() {
+                        break;
+                    }
+                    parser = (parser + 1) % threads;
+                }
+            }
+            if !batch.is_empty() {
+                let _ = parser_queues[parser].send(batch);
+            }
+            drop(parser_queues);
+
+            let (mut summary, outcome) = admission.join().expect("admission thread panicked");
+            for worker in workers {
+                summary.merge(worker.join().expect("worker thread panicked")?);
+            }
+            outcome.map(|_| summary)
+        })
+    }
+
+    /*
+    Description: The admission stage of process_rows_parallel. The parsers got the batches in turn,
+                 taking them back in the same turn gives the rows in input order. Every row is
+                 admitted and journaled and then queued for the worker of its shard. Returns the
+                 rows read and rejected here and the error that stopped admission, if any.
+    Parameters:
+        parsed_queues: Vec<Receiver>  The output of each parser, in the order batches were handed out
+        queues: Vec<SyncSender>  The input of each worker, one per shard
+        source: &Source  The input the rows were read from
+    */
+    fn admit_in_order(&self, parsed_queues: Vec<mpsc::Receiver<Vec<AdmittedRow>>>, queues: Vec<mpsc::SyncSender<Vec<AdmittedRow>>>, source: &Source) -> (RunSummary, Result<(), EngineError>) {
+        let shards = queues.len();
+        let mut summary = RunSummary::default();
+        let mut batches: Vec<Vec<AdmittedRow>> = (0..shards).map(|_| Vec::with_capacity(WORKER_BATCH_SIZE)).collect();
+        let mut outcome = Ok(());
+        'batches: for parsed in parsed_queues.iter().cycle() {
+            // The parser whose turn it is has nothing left once the reader is done
+            let Ok(parsed) = parsed.recv() else {
+                break;
+            };
+            for row in parsed {
+                summary.rows_read += 1;
+                let row = self.admit_parsed(row);
+                outcome = self.journal_row(&row, source).and_then(|_| self.note_adjustment(&mut summary, &row, source));
+                if outcome.is_err() {
+                    break 'batches;
+                }
+                let shard = match &row.transaction {
+                    Ok(transaction) => transaction.client as usize % shards,
+                    Err(err) => {
+                        outcome = self.reject(&mut summary, &row, source, err.clone());
+                        if outcome.is_err() {
+                            break 'batches;
+                        }
+                        continue;
+                    }
+                };
+                batches[shard].push(row);
+                // A failed send means the worker stopped early, its error is picked up on join
+                if batches[shard].len() == WORKER_BATCH_SIZE
+                    && queues[shard].send(mem::take(&mut batches[shard])).is_err()
+                {
+                    break 'batches;
+                }
+            }
+        }
+        for (queue, batch) in queues.into_iter().zip(batches) {
+            if !batch.is_empty() {
+                let _ = queue.send(batch);
+            }
+        }
+        (summary, outcome)
+    }
+
+    /*
+    Description: Applies the batches of one shard in the order they were queued. Returns the
+                 rejections the worker counted, rows read are counted by the reader.
+    */
+    fn run_worker(&self, receiver: mpsc::Receiver<Vec<AdmittedRow>>, source: &Source) -> Result<RunSummary, EngineError> {
+        let mut summary = RunSummary::default();
+        for batch in receiver {
+            for row in batch {
+                if let Err(err) = row.apply(self) {
+                    self.reject(&mut summary, &row, source, err)?;
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /*
+    Description: The line of a row that still has to be applied, None for a row that was recovered
+                 from the journal. A row whose position is unknown is never skipped, it is read
+                 with UNKNOWN_LINE.
+    */
+    fn line_to_read(&self, row: &csv::Result<csv::StringRecord>, source: &Source) -> Option<u64> {
+        match row_line(row) {
+            Some(line) if source.position(line) <= self.resume_after => None,
+            Some(line) => Some(line),
+            None => Some(UNKNOWN_LINE),
+        }
+    }
+
+    /*
+    Description: Parses a row read from the input and admits it
+    Parameters:
+        row: csv::Result<csv::StringRecord>  The row as returned by the csv reader
+        line: u64  Line number of the row in the input
+        source: &Source  The input the row was read from, with the column names it is read with
+    */
+    fn admit_row(&self, row: csv::Result<csv::StringRecord>, line: u64, source: &Source) -> AdmittedRow {
+        self.admit_parsed(self.parse_row(row, line, source))
+    }
+
+    /*
+    Description: Parses a row read from the input. The row is not admitted yet, parse_row only
+                 reads the configuration so rows can be parsed on any thread.
+    */
+    fn parse_row(&self, row: csv::Result<csv::StringRecord>, line: u64, source: &Source) -> AdmittedRow {
+        match row {
+            Ok(record) => self.parse_record(record, &source.header, line),
+            Err(err) => AdmittedRow {
+                line,
+                record: None,
+                transaction: Err(EngineError::ParseError { row: line, reason: err.to_string() }),
+                admission: Ok(()),
+                adjustment: None,
+            },
+        }
+    }
+
+    /*
+    Description: Parses a record that was read in the columns of header
+    */
+    fn parse_record(&self, record: csv::StringRecord, header: &csv::StringRecord, line: u64) -> AdmittedRow {
+        let (transaction, adjustment) = match parse_transaction(&record, header, line, &self.config.amounts) {
+            Ok((transaction, adjustment)) => (Ok(transaction), adjustment),
+            Err(err) => (Err(err), None),
+        };
+        AdmittedRow { line, record: Some(record), transaction, admission: Ok(()), adjustment }
+    }
+
+    /*
+    Description: Admits a parsed row against the tx id history. Rows have to be admitted in input
+                 order, on one thread.
+    */
+    fn admit_parsed(&self, mut row: AdmittedRow) -> AdmittedRow {
+        if let Ok(transaction) = &row.transaction {
+            row.admission = self.admit(transaction);
+        }
+        row
+    }
+
+    /*
+    Description: Writes a row to the journal before it is applied, when a journal was set. The row
+                 is written with the columns of TRANSACTION_COLUMNS so it can be replayed without
+                 the input's header. Rows that could not be read or parsed are journaled with their
+                 error, they never change the state but a resumed run still reports them.
+    */
+    fn journal_row(&self, row: &AdmittedRow, source: &Source) -> Result<(), EngineError> {
+        let mut journal = self.journal.lock().expect("journal lock poisoned");
+        let Some(journal) = journal.as_mut() else {
+            return Ok(());
+        };
+        match &row.transaction {
+            Ok(transaction) => {
+                let record = transaction.to_record();
+                journal.append(source.position(row.line), &record, row.record.as_ref().unwrap_or(&record))
+            }
+            Err(err) => journal.append_rejected(source.position(row.line), row.record.as_ref(), err),
+        }
+    }
+
+    /*
+    Description: Logs, counts and reports a row that was not applied. A broken invariant means the
+                 balances can't be trusted any more, it is reported and then stops the run.
+    */
+    fn reject(&self, summary: &mut RunSummary, row: &AdmittedRow, source: &Source, err: EngineError) -> Result<(), EngineError> {
+        let rejection = |err| row.rejection(source, err);
+        if let EngineError::InvariantViolation { .. } = err {
+            error!("Stopping at {}: {}", source.describe(row.line), err);
+            summary.record_rejection(&err);
+            self.report_rejection(&rejection(err.clone()))?;
+            return Err(err);
+        }
+        warn!("Rejected {}: {}", source.describe(row.line), err);
+        summary.record_rejection(&err);
+        self.report_rejection(&rejection(err))
+    }
+
+    /*
+    Description: Logs, counts and reports an amount that was rounded or truncated by the precision
+                 policy. The row is still applied, so it goes to the adjustments report and stays
+                 out of the rejects report unless applying it fails.
+    */
+    fn note_adjustment(&self, summary: &mut RunSummary, row: &AdmittedRow, source: &Source) -> Result<(), EngineError> {
+        let (Some(adjustment), Ok(transaction)) = (&row.adjustment, &row.transaction) else {
+            return Ok(());
+        };
+        warn!("Adjusted {}: {}", source.describe(row.line), adjustment);
+        summary.amounts_adjusted += 1;
+        match self.adjustments.lock().expect("adjustments writer lock poisoned").as_mut() {
+            Some(writer) => writer.write(&Adjustment {
+                file: source.name.clone(),
+                line: row.line,
+                record: row.record.as_ref().map_or_else(String::new, encode_record),
+                tx: transaction.tx,
+                client: transaction.client,
+                amount: adjustment.amount.clone(),
+                adjusted: adjustment.adjusted,
+                policy: adjustment.policy,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /*
+    Description: Writes a rejected row to the rejects report, when one was configured
+    */
+    fn report_rejection(&self, rejection: &Rejection) -> Result<(), EngineError> {
+        match self.rejects.lock().expect("rejects writer lock poisoned").as_mut() {
+            Some(writer) => writer.write(rejection),
+            None => Ok(()),
+        }
+    }
+
+    /*
+    Description: Applies a single transaction to its client, creating the client on first use.
+    Parameters:
+        transaction: &Transaction  Reference to Transaction struct
+    */
+    pub(crate) fn process_transaction(&self, transaction: &Transaction) -> Result<(), EngineError> {
+        let mut client_entry = self.client_entry(transaction.client);
+
+        let outcome = match transaction.kind {
+            TransactionKind::Deposit => self.process_deposit(&mut client_entry, transaction),
+            TransactionKind::Withdrawal => self.process_withdrawal(&mut client_entry, transaction),
+            TransactionKind::Dispute => self.process_dispute(&mut client_entry, transaction),
+            TransactionKind::Resolve => self.process_resolve(&mut client_entry, transaction),
+            TransactionKind::Chargeback => self.process_chargeback(&mut client_entry, transaction),
+            TransactionKind::Lock | TransactionKind::Unlock | TransactionKind::Adjust => {
+                self.process_admin(&mut client_entry, transaction)
+            }
+        };
+        if self.config.check_invariants || cfg!(debug_assertions) {
+            client_entry.check_invariants().map_err(|reason| EngineError::InvariantViolation {
+                tx: transaction.tx,
+                client: transaction.client,
+                reason,
+            })?;
+        }
+        outcome
+    }
+
+    /*
+    Description: Finds a client, creating it on first use
+    */
+    fn client_entry(&self, client: u16) -> RefMut<'_, u16, Client> {
+        self.clients.entry(client).or_insert_with(|| {
+            info!("Creating new client: {}", client);
+            Client::new(client)
+        })
+    }
+
+    /*
+    Description: Fails with AccountLocked when the client was locked by a chargeback. Transactions
+                 performed on a locked account are rejected.
+    */
+    fn ensure_unlocked(client_entry: &Client, transaction: &Transaction) -> Result<(), EngineError> {
+        if client_entry.is_account_locked(transaction.tx) {
+            return Err(EngineError::AccountLocked { client: client_entry.id() });
+        }
+        Ok(())
+    }
+
+    /*
+    Description: Finds the stored tx referenced by a dispute, resolve or chargeback and checks
+                 that it belongs to the client on the row. Without the ownership check a client
+                 could freeze another client's funds.
+    Parameters:
+        transaction: &Transaction  Reference to the dispute, resolve or chargeback row
+    */
+    fn referenced_entry(&self, transaction: &Transaction) -> Result<LedgerEntry, EngineError> {
+        let entry = self.transactions
+            .get(transaction.tx)?
+            .ok_or(EngineError::UnknownTx { tx: transaction.tx })?;
+        if entry.client != transaction.client {
+            return Err(EngineError::ClientMismatch {
+                tx: transaction.tx,
+                owner: entry.client,
+                client: transaction.client,
+            });
+        }
+        Ok(entry)
+    }
+
+    /*
+    Description: Checks a row against the history as soon as it is read, before it is applied.
+                 These are the only checks that involve other clients, making them in input order
+                 keeps the outcome independent of which worker gets to a shared tx id first.
+                 A deposit or withdrawal claims its tx id, the first row that uses an id owns it
+                 whether or not it can be applied. A row reusing an id is handled according to the
+                 configured DuplicatePolicy.
+                 A dispute, resolve or chargeback has to reference a tx that was claimed by the
+                 same client.
+    Parameters:
+        transaction: &Transaction  Reference to the row that was read
+    */
+    fn admit(&self, transaction: &Transaction) -> Result<(), EngineError> {
+        let Some(kind) = transaction.kind.entry_kind() else {
+            return match transaction.kind {
+                TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::Chargeback => {
+                    self.referenced_entry(transaction).map(|_| ())
+                }
+                _ => Ok(()),
+            };
+        };
+        let claimed = self.transactions.insert(transaction.tx, LedgerEntry {
+            client: transaction.client,
+            kind,
+            amount: transaction.amount.unwrap_or(Money::ZERO),
+            state: TxState::Pending,
+        })?;
+        if claimed {
+            return Ok(());
+        }
+        match self.config.duplicate_policy {
+            DuplicatePolicy::Reject => Err(EngineError::DuplicateTx { tx: transaction.tx }),
+            DuplicatePolicy::Warn => {
+                warn!(
+                    "Transaction id {} for client {} was already used, applying it anyway.",
                     transaction.tx, transaction.client
                 );
+                Ok(())
             }
-            Err(err) => {
-                error!(
-                    "Failed to resolve transaction {} for client {}: {}",
-                    transaction.tx, transaction.client, err
-                );
+            DuplicatePolicy::Allow => Ok(()),
+        }
+    }
+
+    /*
+    Description: Settles the history entry of an applied or rejected deposit or withdrawal. A
+                 Pending entry of the same client belongs to this row, a duplicate that was let
+                 through finds the original already settled and leaves it alone. Rows that were
+                 never claimed (the process_* functions called directly) are stored when applied.
+    Parameters:
+        transaction: &Transaction  Reference to the deposit or withdrawal row
+        kind: EntryKind  Whether the row was a deposit or a withdrawal
+        outcome: &Result<Money, EngineError>  The amount applied, or why the row was rejected
+    */
+    fn record(&self, transaction: &Transaction, kind: EntryKind, outcome: &Result<Money, EngineError>) -> Result<(), EngineError> {
+        match (self.transactions.get(transaction.tx)?, outcome) {
+            (Some(entry), _) if entry.state == TxState::Pending && entry.client == transaction.client => {
+                let state = if outcome.is_ok() { TxState::Processed } else { TxState::Failed };
+                self.transactions.set_state(transaction.tx, state)
             }
+            (None, Ok(amount)) => {
+                self.transactions.insert(transaction.tx, LedgerEntry {
+                    client: transaction.client,
+                    kind,
+                    amount: *amount,
+                    state: TxState::Processed,
+                })?;
+                Ok(())
+            }
+            _ => Ok(()),
         }
-    } else {
-        warn!(
-            "Transaction {} not found in disputed transactions for client {}. Unable to resolve.",
+    }
+
+    /*
+    Description: Begins the deposit process, first checking if the account is locked. Transactions
+                 performed on a locked account are rejected. Only positive values will be accepted.
+    Parameters:
+        client_entry: &mut Mutable reference to the instance of the client
+        transaction: &Transaction  Reference to Transaction struct
+    */
+    pub(crate) fn process_deposit(&self, client_entry: &mut Client, transaction: &Transaction) -> Result<(), EngineError> {
+        let outcome = Self::ensure_unlocked(client_entry, transaction)
+            .and_then(|_| transaction.amount.ok_or(EngineError::InvalidAmount))
+            .and_then(|amount| client_entry.deposit(Some(amount)).map(|_| amount));
+        self.record(transaction, EntryKind::Deposit, &outcome)?;
+        let amount = outcome?;
+        info!(
+            "Deposit of ${:.2} successful for client {}. New available balance: ${:.2}",
+            amount, client_entry.id(), client_entry.available()
+        );
+        Ok(())
+    }
+
+    /*
+    Description: Begins the withdrawal process, checks for locked account. Only positive values will
+                 be accepted and the client needs enough available funds.
+    Parameters:
+        client_entry: &mut Mutable reference to the instance of the client
+        transaction: &Transaction  Reference to Transaction struct
+    */
+    pub(crate) fn process_withdrawal(&self, client_entry: &mut Client, transaction: &Transaction) -> Result<(), EngineError> {
+        let outcome = Self::ensure_unlocked(client_entry, transaction)
+            .and_then(|_| transaction.amount.ok_or(EngineError::InvalidAmount))
+            .and_then(|amount| client_entry.withdraw(Some(amount)).map(|_| amount));
+        self.record(transaction, EntryKind::Withdrawal, &outcome)?;
+        let amount = outcome?;
+        info!(
+            "Withdrawal of ${:.4} successful for client {}. New available balance: ${:.4}",
+            amount, client_entry.id(), client_entry.available()
+        );
+        Ok(())
+    }
+
+    /*
+    Description: Begins the dispute process. The referenced tx has to be in the engine's history,
+                 belong to the client and not have been disputed before. The account can't be
+                 locked.
+    Parameters:
+        client_entry: &mut Mutable reference to the instance of the client
+        transaction: &Transaction  Reference to Transaction struct
+    */
+    pub(crate) fn process_dispute(&self, client_entry: &mut Client, transaction: &Transaction) -> Result<(), EngineError> {
+        let entry = self.referenced_entry(transaction)?;
+        Self::ensure_unlocked(client_entry, transaction)?;
+        entry.check_transition(transaction.tx, TxState::Disputed)?;
+        client_entry.dispute(transaction.tx, entry.kind, Some(entry.amount), self.config.dispute_policy)?;
+        self.transactions.set_state(transaction.tx, TxState::Disputed)?;
+        info!(
+            "Dispute successful for client {}: {:?} of ${:.4} moved to Held, Available balance is now ${:.4}.",
+            transaction.client, entry.kind, entry.amount, client_entry.available()
+        );
+        Ok(())
+    }
+
+    /*
+    Description: Used on transactions already under dispute, checks for locked account. The tx
+                 has to be in the Disputed state.
+    Parameters:
+        client_entry: &mut Mutable reference to the instance of the client
+        transaction: &Transaction  Reference to Transaction struct
+    */
+    pub(crate) fn process_resolve(&self, client_entry: &mut Client, transaction: &Transaction) -> Result<(), EngineError> {
+        let entry = self.referenced_entry(transaction)?;
+        Self::ensure_unlocked(client_entry, transaction)?;
+        entry.check_transition(transaction.tx, TxState::Resolved)?;
+        client_entry.resolve(transaction.tx)?;
+        self.transactions.set_state(transaction.tx, TxState::Resolved)?;
+        info!(
+            "Transaction {} was resolved {}. Held funds are now available.",
             transaction.tx, transaction.client
         );
+        Ok(())
     }
-}
 
-/*
-Description: Used on transactions already under dispute, checks for locked account. Proceeds to
-             check if transaction id is in the clients disputed_transaction hashmap.
-Parameters:
-    client_entry: &mut Mutable reference to the instance of the client
-    transaction: &Transaction  Reference to Transaction struct
-*/
-pub(crate) fn process_chargeback(client_entry: &mut Client, transaction: &Transaction) {
-    if client_entry.is_account_locked(transaction.tx) {
-        return;
+    /*
+    Description: Used on transactions already under dispute, checks for locked account. The tx
+                 has to be in the Disputed state.
+    Parameters:
+        client_entry: &mut Mutable reference to the instance of the client
+        transaction: &Transaction  Reference to Transaction struct
+    */
+    pub(crate) fn process_chargeback(&self, client_entry: &mut Client, transaction: &Transaction) -> Result<(), EngineError> {
+        let entry = self.referenced_entry(transaction)?;
+        Self::ensure_unlocked(client_entry, transaction)?;
+        entry.check_transition(transaction.tx, TxState::ChargedBack)?;
+        client_entry.chargeback(transaction.tx)?;
+        self.transactions.set_state(transaction.tx, TxState::ChargedBack)?;
+        info!(
+            "Chargeback processed successfully for client {}. Transaction {} reversed.",
+            transaction.client, transaction.tx
+        );
+        Ok(())
     }
-    if client_entry.disputed_transactions().contains_key(&transaction.tx) {
-        match client_entry.chargeback(transaction.tx) {
-            Ok(_) => {
+
+    /*
+    Description: Applies an administrative row. Only runs started with admin rows allowed accept
+                 them, everyone else gets the row rejected as unauthorized. Every admin row that
+                 is applied is written to the log as an AUDIT line, refused ones end up in the
+                 rejects report like any other row. Admin rows work on locked accounts and don't
+                 use up their tx id, it is only kept as a reference in the audit line.
+                 lock:   locks the account
+                 unlock: clears the lock, for example after a chargeback was settled
+                 adjust: adds the signed amount to available and total, a reason is required
+    Parameters:
+        client_entry: &mut Mutable reference to the instance of the client
+        transaction: &Transaction  Reference to Transaction struct
+    */
+    pub(crate) fn process_admin(&self, client_entry: &mut Client, transaction: &Transaction) -> Result<(), EngineError> {
+        let kind = transaction.kind;
+        if !self.config.allow_admin {
+            warn!(
+                "AUDIT refused {} of client {} (tx {}), admin rows are not allowed",
+                kind, transaction.client, transaction.tx
+            );
+            return Err(EngineError::Unauthorized { kind });
+        }
+        match kind {
+            TransactionKind::Adjust => {
+                let reason = transaction.reason.as_deref().ok_or(EngineError::MissingReason)?;
+                let amount = transaction.amount.ok_or(EngineError::InvalidAmount)?;
+                client_entry.adjust(amount)?;
                 info!(
-                    "Chargeback processed successfully for client {}. Transaction {}: ${:?} removed from Held and Total funds.",
-                    transaction.client, transaction.tx, transaction.amount
+                    "AUDIT adjust of client {} (tx {}) by ${}: {}. Available: ${}, Total: ${}",
+                    transaction.client, transaction.tx, amount, reason, client_entry.available(), client_entry.total()
                 );
             }
-            Err(err) => {
-                error!(
-                    "Failed to process chargeback for client {}. Transaction {}: {}",
-                    transaction.client, transaction.tx, err
+            lock_or_unlock => {
+                client_entry.set_locked(lock_or_unlock == TransactionKind::Lock);
+                info!(
+                    "AUDIT {} of client {} (tx {}), locked: {}",
+                    kind, transaction.client, transaction.tx, client_entry.locked()
                 );
             }
         }
-    } else {
-        warn!(
-            "Transaction {} not found in disputed transactions for client {}. Unable to resolve.",
-            transaction.tx, transaction.client
-        );
+        Ok(())
+    }
+}
+
+/*
+Description: Line number a row starts on in the input, as the csv reader saw it. Line numbers are 1
+             based and the header is line 1. A row the reader could not read carries the position
+             in its error, None when the reader doesn't know it.
+*/
+fn row_line(row: &csv::Result<csv::StringRecord>) -> Option<u64> {
+    match row {
+        Ok(record) => record.position(),
+        Err(err) => err.position(),
+    }
+    .map(|position| position.line())
+}
+
+// Line reported for a row whose position the csv reader doesn't know
+const UNKNOWN_LINE: u64 = 0;
+
+// Columns of a transaction row, in the order rows are journaled
+pub(crate) const TRANSACTION_COLUMNS: [&str; 5] = ["type", "client", "tx", "amount", "reason"];
+// Columns every input has to have, amount and reason are only needed by some rows
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+/*
+Description: Checks the header row of an input and returns the column names rows are read with.
+             Columns are found by name, so they can come in any order and columns the engine
+             doesn't use are ignored. Names are matched without regard to case. An empty input
+             has no header and no rows, it is read as if it had the usual columns.
+Parameters:
+    header: &csv::StringRecord  The first row of the input
+*/
+pub(crate) fn read_header(header: &csv::StringRecord) -> Result<csv::StringRecord, EngineError> {
+    if header.is_empty() {
+        return Ok(csv::StringRecord::from(TRANSACTION_COLUMNS.to_vec()));
     }
-}
\ No newline at end of file
+    let header: csv::StringRecord = header.iter().map(str::to_lowercase).collect();
+    for column in REQUIRED_COLUMNS {
+        if !header.iter().any(|name| name == column) {
+            return Err(EngineError::ParseError { row: 1, reason: format!("the header has no {} column", column) });
+        }
+    }
+    Ok(header)
+}
+
+/*
+    An amount that the precision policy rounded or truncated. It is not an error, the row is
+    applied with the adjusted amount and the change is logged and written to the adjustments report
+    so it can be traced.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AmountAdjustment {
+    // The amount as it was written in the row
+    pub amount: String,
+    // The amount that was applied
+    pub adjusted: Money,
+    pub policy: PrecisionPolicy,
+}
+
+impl fmt::Display for AmountAdjustment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "amount {} was changed to {} by the {} policy", self.amount, self.adjusted, self.policy)
+    }
+}
+
+/*
+Description: Reads a row into a Transaction by the names in the header. A row may stop early, the
+             missing trailing columns are read as empty, which is how dispute rows without an
+             amount are usually written. A value that can't be read is reported with its column.
+             The amount is read under the amount rules first. When the precision policy rounded or
+             truncated it, the transaction carries the new amount and the change is returned
+             with it.
+Parameters:
+    record: &csv::StringRecord  The row
+    header: &csv::StringRecord  The column names returned by read_header
+    row: u64  Line number of the row, for the error
+    rules: &AmountRules  How the amount is validated
+*/
+pub(crate) fn parse_transaction(record: &csv::StringRecord, header: &csv::StringRecord, row: u64, rules: &AmountRules) -> Result<(Transaction, Option<AmountAdjustment>), EngineError> {
+    let mut fields: Vec<&str> = record.iter().collect();
+    fields.resize(fields.len().max(header.len()), "");
+
+    let mut adjustment = None;
+    let canonical;
+    if let Some(index) = header.iter().position(|name| name == "amount").filter(|&index| !fields[index].is_empty()) {
+        let amount = fields[index];
+        let parsed = Money::parse_amount(amount, rules)
+            .map_err(|err| unsupported_type(record, header).unwrap_or_else(|| amount_error(err, amount, row)))?;
+        if parsed.adjusted {
+            adjustment = Some(AmountAdjustment {
+                amount: amount.to_string(),
+                adjusted: parsed.amount,
+                policy: rules.excess_precision,
+            });
+        }
+        canonical = parsed.amount.to_string();
+        fields[index] = &canonical;
+    }
+
+    let record = csv::StringRecord::from(fields);
+    record
+        .deserialize(Some(header))
+        .map(|transaction| (transaction, adjustment))
+        .map_err(|err| row_error(err, &record, header, row))
+}
+
+/*
+Description: Turns an amount that failed the amount rules into a typed error
+*/
+fn amount_error(err: MoneyError, amount: &str, row: u64) -> EngineError {
+    match err {
+        MoneyError::TooPrecise { max, .. } => EngineError::ExcessPrecision { amount: amount.to_string(), max_places: max },
+        err => EngineError::ParseError { row, reason: err.to_string() },
+    }
+}
+
+/*
+Description: Turns a failed row into a typed error. A type the engine doesn't know is reported as
+             unsupported_type, anything else as a parse_error naming the column when the csv
+             reader knows it.
+*/
+fn row_error(err: csv::Error, record: &csv::StringRecord, header: &csv::StringRecord, row: u64) -> EngineError {
+    if let Some(unsupported) = unsupported_type(record, header) {
+        return unsupported;
+    }
+    let reason = match err.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => match err.field().and_then(|field| header.get(field as usize)) {
+            Some(column) => format!("invalid {}: {}", column, err.kind()),
+            None => err.kind().to_string(),
+        },
+        _ => err.to_string(),
+    };
+    EngineError::ParseError { row, reason }
+}
+
+/*
+Description: The unsupported_type error of a row whose type column is set to a type the engine
+             doesn't know, it takes precedence over any other problem with the row
+*/
+fn unsupported_type(record: &csv::StringRecord, header: &csv::StringRecord) -> Option<EngineError> {
+    column_value(record, header, "type")
+        .filter(|kind| !kind.is_empty())
+        .and_then(|kind| kind.parse::<TransactionKind>().err())
+}
+
+/*
+Description: The value of a column of a row, found by its name in the header
+*/
+pub(crate) fn column_value<'a>(record: &'a csv::StringRecord, header: &csv::StringRecord, column: &str) -> Option<&'a str> {
+    header.iter().position(|name| name == column).and_then(|index| record.get(index))
+}
@@ -0,0 +1,863 @@
+use crate::adjustments::AdjustmentsWriter;
+use crate::client::Client;
+use crate::config::{AmountRules, DisputePolicy, DuplicatePolicy, EngineConfig, OutputOptions, PrecisionPolicy, SortOrder};
+use crate::errors::EngineError;
+use crate::money::Money;
+use crate::rejects::RejectsWriter;
+use crate::transactions::*;
+use crate::output::write_accounts;
+use std::fs;
+use test_case::test_case;
+
+/*
+    Transactions are fed from an in-memory byte slice instead of a csv file on disk.
+ */
+#[test]
+fn test_process_transactions_from_reader() {
+    let input = "\
+type, client, tx, amount
+deposit, 1, 1, 10.5
+deposit, 1, 2, 2.25
+withdrawal, 1, 3, 1.0
+deposit, 2, 4, 3.0
+";
+    let engine = PaymentsEngine::new();
+    engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(engine.clients().len(), 2);
+    assert_eq!(engine.clients().get(&1).unwrap().available(), "11.75".parse::<Money>().unwrap());
+    assert_eq!(engine.clients().get(&2).unwrap().total(), "3".parse::<Money>().unwrap());
+}
+
+#[test]
+fn test_process_transactions_from_empty_reader() {
+    let engine = PaymentsEngine::new();
+    engine.process_transactions_from_reader("type,client,tx,amount\n".as_bytes()).unwrap();
+    assert!(engine.clients().is_empty());
+}
+
+/*
+    Two engines in the same process must not share history. The dispute in the second ledger
+    references a tx id that only exists in the first one and has to be ignored.
+ */
+#[test]
+fn test_engines_are_isolated() {
+    let first = PaymentsEngine::new();
+    first.process_transactions_from_reader("type,client,tx,amount\ndeposit,1,1,5.0\n".as_bytes()).unwrap();
+
+    let second = PaymentsEngine::new();
+    second.process_transactions_from_reader("type,client,tx,amount\ndeposit,1,2,5.0\ndispute,1,1,\n".as_bytes()).unwrap();
+
+    let client = second.clients().get(&1).unwrap();
+    assert_eq!(client.held(), Money::ZERO);
+    assert_eq!(client.available(), "5".parse::<Money>().unwrap());
+    assert_eq!(first.clients().get(&1).unwrap().held(), Money::ZERO);
+}
+
+#[test]
+fn test_dispute_uses_engine_history() {
+    let engine = PaymentsEngine::new();
+    engine.process_transactions_from_reader("type,client,tx,amount\ndeposit,1,1,5.0\ndispute,1,1,\n".as_bytes()).unwrap();
+
+    let client = engine.clients().get(&1).unwrap();
+    assert_eq!(client.held(), "5".parse::<Money>().unwrap());
+    assert_eq!(client.available(), Money::ZERO);
+}
+
+/*
+    A disputed withdrawal must not take the amount out of available a second time, the funds are
+    held as a pending reversal credit instead. The chargeback then returns them to the client.
+ */
+#[test]
+fn test_withdrawal_dispute_and_chargeback() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,30.0
+dispute,1,2,
+";
+    let engine = PaymentsEngine::new();
+    engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+    {
+        let client = engine.clients().get(&1).unwrap();
+        assert_eq!(client.available(), "70".parse::<Money>().unwrap());
+        assert_eq!(client.held(), "30".parse::<Money>().unwrap());
+        assert_eq!(client.total(), "100".parse::<Money>().unwrap());
+    }
+
+    engine.process_transactions_from_reader("type,client,tx,amount\nchargeback,1,2,\n".as_bytes()).unwrap();
+    let client = engine.clients().get(&1).unwrap();
+    assert_eq!(client.available(), "100".parse::<Money>().unwrap());
+    assert_eq!(client.held(), Money::ZERO);
+    assert!(client.locked());
+}
+
+/*
+    Client 2 references client 1's deposit on every step of a dispute. All three rows are
+    rejected, client 1's funds stay available and the rejections are counted in the summary.
+ */
+#[test]
+fn test_disputes_only_reference_own_transactions() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,100.0
+deposit,2,2,100.0
+dispute,2,1,
+resolve,2,1,
+chargeback,2,1,
+";
+    let engine = PaymentsEngine::new();
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(summary.rows_read, 5);
+    assert_eq!(summary.rows_rejected, 3);
+    assert_eq!(summary.rejections.get("client_mismatch"), Some(&3));
+    for client_id in [1, 2] {
+        let client = engine.clients().get(&client_id).unwrap();
+        assert_eq!(client.available(), "100".parse::<Money>().unwrap());
+        assert_eq!(client.held(), Money::ZERO);
+        assert!(!client.locked());
+    }
+
+    // The rejected rows must not hide the deposit from its real owner
+    engine.process_transactions_from_reader("type,client,tx,amount\ndispute,1,1,\n".as_bytes()).unwrap();
+    assert_eq!(engine.clients().get(&1).unwrap().held(), "100".parse::<Money>().unwrap());
+}
+
+#[test]
+fn test_client_mismatch_error() {
+    let engine = PaymentsEngine::new();
+    engine.process_transactions_from_reader("type,client,tx,amount\ndeposit,1,1,5.0\n".as_bytes()).unwrap();
+
+    let transaction = Transaction::builder(TransactionKind::Dispute, 2, 1).build();
+    let mut client = Client::new(2);
+    assert_eq!(
+        engine.process_dispute(&mut client, &transaction),
+        Err(EngineError::ClientMismatch { tx: 1, owner: 1, client: 2 })
+    );
+}
+
+/*
+    A replayed deposit with the same tx id is handled by the duplicate policy. The history keeps
+    the first deposit in every case.
+   I.   Reject: the replay is not applied and is counted
+   II.  Warn: the replay is applied
+   III. Allow: the replay is applied
+ */
+#[test_case(DuplicatePolicy::Reject, "10", 1)]
+#[test_case(DuplicatePolicy::Warn, "30", 0)]
+#[test_case(DuplicatePolicy::Allow, "30", 0)]
+fn test_duplicate_policy(duplicate_policy: DuplicatePolicy, expected_total: &str, expected_rejections: usize) {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,1,20.0
+";
+    let engine = PaymentsEngine::with_config(EngineConfig { duplicate_policy, ..EngineConfig::default() });
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(summary.rows_rejected, expected_rejections);
+    assert_eq!(engine.clients().get(&1).unwrap().total(), expected_total.parse::<Money>().unwrap());
+
+    // Disputing tx 1 holds the amount of the first deposit
+    engine.process_transactions_from_reader("type,client,tx,amount\ndispute,1,1,\n".as_bytes()).unwrap();
+    assert_eq!(engine.clients().get(&1).unwrap().held(), "10".parse::<Money>().unwrap());
+}
+
+/*
+    Dispute, resolve and chargeback rows reuse the tx id of the transaction they reference. They
+    must not replace it in the history or be treated as duplicates.
+ */
+#[test]
+fn test_dispute_rows_are_not_stored() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,1,2,5.0
+dispute,1,1,
+resolve,1,1,
+dispute,1,2,
+";
+    let engine = PaymentsEngine::new();
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(summary.rows_rejected, 0);
+    assert_eq!(engine.clients().get(&1).unwrap().held(), "5".parse::<Money>().unwrap());
+}
+
+/*
+    Rows that can't be applied come back as typed errors and are counted by reason code. Line 3
+    has an invalid client id and line 4 an unknown transaction type.
+ */
+#[test]
+fn test_rejections_are_counted_by_reason() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,x,2,10.0
+refund,1,3,10.0
+withdrawal,1,4,50.0
+dispute,1,99,
+resolve,1,1,
+";
+    let engine = PaymentsEngine::new();
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(summary.rows_read, 6);
+    assert_eq!(summary.rows_rejected, 5);
+    assert_eq!(summary.rejections.get("parse_error"), Some(&1));
+    assert_eq!(summary.rejections.get("unsupported_type"), Some(&1));
+    assert_eq!(summary.rejections.get("insufficient_funds"), Some(&1));
+    assert_eq!(summary.rejections.get("unknown_tx"), Some(&1));
+    assert_eq!(summary.rejections.get("not_disputed"), Some(&1));
+}
+
+/*
+    Rows are read by the column names in the header
+    i.    The usual columns
+    ii.   Columns in another order
+    iii.  Extra columns are ignored
+    iv.   A dispute without its trailing amount column
+    v.    Header names in any case
+    vi.   A malformed amount is an error, not a missing amount
+    vii.  A tx that is not a number, reported with its column
+    viii. A row that stops before its client
+    ix.   An unknown type
+ */
+#[test_case("type,client,tx,amount", "deposit,1,2,1.5", Ok(("deposit", 1, 2, Some("1.5"))))]
+#[test_case("amount,tx,type,client", "1.5,2,deposit,1", Ok(("deposit", 1, 2, Some("1.5"))))]
+#[test_case("type,client,tx,amount,branch", "deposit,1,2,1.5,north pole", Ok(("deposit", 1, 2, Some("1.5"))))]
+#[test_case("type,client,tx,amount", "dispute,1,2", Ok(("dispute", 1, 2, None)))]
+#[test_case("Type,CLIENT,Tx,Amount", "deposit,1,2,1.5", Ok(("deposit", 1, 2, Some("1.5"))))]
+#[test_case("type,client,tx,amount", "deposit,1,2,12.3.4", Err(EngineError::ParseError { row: 3, reason: "invalid amount: \"12.3.4\" is not a plain decimal number".to_string() }))]
+#[test_case("type,client,tx,amount", "deposit,1,not a tx,1.0", Err(EngineError::ParseError { row: 3, reason: "invalid tx: invalid digit found in string".to_string() }))]
+#[test_case("type,client,tx,amount", "deposit", Err(EngineError::ParseError { row: 3, reason: "invalid client: cannot parse integer from empty string".to_string() }))]
+#[test_case("type,client,tx,amount", "refund,1,2,1.0", Err(EngineError::UnsupportedType { transaction_type: "refund".to_string() }))]
+fn test_parse_transaction(header: &str, row: &str, expected: Result<(&str, u16, u32, Option<&str>), EngineError>) {
+    let header = read_header(&header.split(',').collect()).unwrap();
+    let record: csv::StringRecord = row.split(',').collect();
+    let parsed = parse_transaction(&record, &header, 3, &AmountRules::default()).map(|(transaction, _)| {
+        (transaction.kind().to_string(), transaction.client(), transaction.tx(), transaction.amount())
+    });
+    let expected = expected.map(|(kind, client, tx, amount)| {
+        (kind.to_string(), client, tx, amount.map(|amount| amount.parse::<Money>().unwrap()))
+    });
+    assert_eq!(parsed, expected);
+}
+
+/*
+    An input whose header lacks a column every row needs is refused before any row is read
+ */
+#[test]
+fn test_header_without_tx_column() {
+    let engine = PaymentsEngine::new();
+    assert_eq!(
+        engine.process_transactions_from_reader("type,client,amount\ndeposit,1,1.0\n".as_bytes()),
+        Err(EngineError::ParseError { row: 1, reason: "the header has no tx column".to_string() })
+    );
+    assert!(engine.client(1).is_none());
+}
+
+/*
+    A run over an input with its own column order journals the rows in the usual order, so the
+    rows can be replayed and the rejects report still finds tx and client by name.
+ */
+#[test]
+fn test_reordered_columns_end_to_end() {
+    let input = "\
+tx,client,amount,type,note
+1,1,10.0,deposit,first
+2,1,50.0,withdrawal,too much
+1,1,,dispute
+";
+    let rejects_file = tempfile::NamedTempFile::new().unwrap();
+    let rejects_path = rejects_file.path().to_str().unwrap();
+    let mut engine = PaymentsEngine::new();
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path).unwrap());
+    engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    let client = engine.client(1).unwrap();
+    assert_eq!(client.held(), "10".parse::<Money>().unwrap());
+    assert_eq!(
+        std::fs::read_to_string(rejects_path).unwrap(),
+        "\
+file,line,record,tx,client,reason,detail
+,3,\"2,1,50.0,withdrawal,too much\",2,1,insufficient_funds,insufficient available funds
+"
+    );
+}
+
+/*
+    Amounts with more decimal places than allowed, end to end through the rejects and adjustments
+    reports
+    i.    Rejected with excess_precision
+    ii.   Rounded half to even and applied, listed as an adjustment and not as a rejection
+    iii.  Truncated and applied, listed as an adjustment and not as a rejection
+ */
+#[test_case(PrecisionPolicy::Reject, "1", "\
+file,line,record,tx,client,reason,detail
+,3,\"deposit,1,2,0.00015\",2,1,excess_precision,amount 0.00015 has more than 4 decimal places
+", "\
+file,line,record,tx,client,amount,adjusted,policy
+" ; "reject")]
+#[test_case(PrecisionPolicy::RoundHalfEven, "1.0002", "\
+file,line,record,tx,client,reason,detail
+", "\
+file,line,record,tx,client,amount,adjusted,policy
+,3,\"deposit,1,2,0.00015\",2,1,0.00015,0.0002,round-half-even
+" ; "round half even")]
+#[test_case(PrecisionPolicy::Truncate, "1.0001", "\
+file,line,record,tx,client,reason,detail
+", "\
+file,line,record,tx,client,amount,adjusted,policy
+,3,\"deposit,1,2,0.00015\",2,1,0.00015,0.0001,truncate
+" ; "truncate")]
+fn test_excess_precision_policy(excess_precision: PrecisionPolicy, expected_total: &str, expected_rejects: &str, expected_adjustments: &str) {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,1
+deposit,1,2,0.00015
+";
+    let rejects_file = tempfile::NamedTempFile::new().unwrap();
+    let rejects_path = rejects_file.path().to_str().unwrap();
+    let adjustments_file = tempfile::NamedTempFile::new().unwrap();
+    let adjustments_path = adjustments_file.path().to_str().unwrap();
+    let mut engine = PaymentsEngine::with_config(EngineConfig {
+        amounts: AmountRules { excess_precision, ..AmountRules::default() },
+        ..EngineConfig::default()
+    });
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path).unwrap());
+    engine.set_adjustments_writer(AdjustmentsWriter::from_path(adjustments_path).unwrap());
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(engine.client(1).unwrap().total(), expected_total.parse::<Money>().unwrap());
+    assert_eq!(summary.amounts_adjusted, usize::from(excess_precision != PrecisionPolicy::Reject));
+    assert_eq!(summary.rows_rejected, usize::from(excess_precision == PrecisionPolicy::Reject));
+    assert_eq!(std::fs::read_to_string(rejects_path).unwrap(), expected_rejects);
+    assert_eq!(std::fs::read_to_string(adjustments_path).unwrap(), expected_adjustments);
+}
+
+/*
+    A rounded amount is what gets applied and journaled, so the journal replays the same balance
+ */
+#[test]
+fn test_rounded_amount_is_applied() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,2.00015
+withdrawal,1,2,1.5e0
+";
+    let engine = PaymentsEngine::with_config(EngineConfig {
+        amounts: AmountRules { allow_exponent: true, excess_precision: PrecisionPolicy::RoundHalfEven, ..AmountRules::default() },
+        ..EngineConfig::default()
+    });
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(summary.rows_rejected, 0);
+    assert_eq!(summary.amounts_adjusted, 1);
+    assert_eq!(engine.client(1).unwrap().available(), "0.5002".parse::<Money>().unwrap());
+}
+
+/*
+    Several files share one ledger: a dispute in the second file holds a deposit from the first, and
+    the rejects report names the file each row came from
+ */
+#[test]
+fn test_process_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let day1 = dir.path().join("day1.csv");
+    let day2 = dir.path().join("day2.csv");
+    fs::write(&day1, "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,50.0\n").unwrap();
+    fs::write(&day2, "tx,client,type,amount\n1,1,dispute,\n1,1,deposit,3.0\n").unwrap();
+    let files = [day1.to_str().unwrap(), day2.to_str().unwrap()];
+    let rejects_file = tempfile::NamedTempFile::new().unwrap();
+    let rejects_path = rejects_file.path().to_str().unwrap();
+    let mut engine = PaymentsEngine::new();
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path).unwrap());
+    let summary = engine.process_files(&files).unwrap();
+
+    assert_eq!((summary.rows_read, summary.rows_rejected), (4, 2));
+    assert_eq!(engine.client(1).unwrap().held(), "10".parse::<Money>().unwrap());
+    assert_eq!(
+        fs::read_to_string(rejects_path).unwrap(),
+        format!(
+            "\
+file,line,record,tx,client,reason,detail
+{},3,\"withdrawal,1,2,50.0\",2,1,insufficient_funds,insufficient available funds
+{},3,\"1,1,deposit,3.0\",1,1,duplicate_tx,transaction id 1 has already been used
+",
+            files[0], files[1]
+        )
+    );
+}
+
+/*
+    A file that can't be opened stops the run, the files before it stay applied
+ */
+#[test]
+fn test_process_files_stops_at_missing_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let day1 = dir.path().join("day1.csv");
+    fs::write(&day1, "type,client,tx,amount\ndeposit,1,1,10.0\n").unwrap();
+    let engine = PaymentsEngine::new();
+    let outcome = engine.process_files(&[day1.to_str().unwrap(), "does_not_exist.csv"]);
+
+    assert!(matches!(outcome, Err(EngineError::Io(_))));
+    assert_eq!(engine.client(1).unwrap().total(), "10".parse::<Money>().unwrap());
+}
+
+/*
+    A row the csv reader can't read is reported on the line the reader found it, even after a
+    record that spans two lines
+ */
+#[test]
+fn test_unreadable_row_keeps_its_line() {
+    let mut input = b"type,client,tx,amount,reason\ndeposit,1,1,10.0,\"first\nsecond\"\ndeposit,1,2,1.0,\n".to_vec();
+    input.extend_from_slice(b"deposit,1,3,1.0,\xff\n");
+    let rejects_file = tempfile::NamedTempFile::new().unwrap();
+    let rejects_path = rejects_file.path().to_str().unwrap();
+    let mut engine = PaymentsEngine::new();
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path).unwrap());
+    let summary = engine.process_transactions_from_reader(input.as_slice()).unwrap();
+
+    assert_eq!((summary.rows_read, summary.rows_rejected), (3, 1));
+    let rejects = fs::read_to_string(rejects_path).unwrap();
+    let rejected: Vec<&str> = rejects.lines().skip(1).collect();
+    assert_eq!(rejected.len(), 1);
+    assert!(rejected[0].starts_with(",5,,,,parse_error,\"row 5:"), "{}", rejected[0]);
+}
+
+#[test]
+fn test_missing_input_file_is_an_io_error() {
+    let engine = PaymentsEngine::new();
+    assert!(matches!(
+        engine.process_transactions("does_not_exist.csv"),
+        Err(EngineError::Io(_))
+    ));
+}
+
+/*
+    Every row that is not applied ends up in the rejects report with its line number, the raw
+    record, tx, client and reason code.
+ */
+#[test]
+fn test_rejects_report() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,x,2,10.0
+refund,1,3,10.0
+withdrawal,1,4,50.0
+";
+    let rejects_file = tempfile::NamedTempFile::new().unwrap();
+    let rejects_path = rejects_file.path().to_str().unwrap();
+    let mut engine = PaymentsEngine::new();
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path).unwrap());
+    engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    let report = std::fs::read_to_string(rejects_path).unwrap();
+    assert_eq!(
+        report,
+        "\
+file,line,record,tx,client,reason,detail
+,3,\"deposit,x,2,10.0\",2,,parse_error,row 3: invalid client: invalid digit found in string
+,4,\"refund,1,3,10.0\",3,1,unsupported_type,\"unsupported transaction type \"\"refund\"\"\"
+,5,\"withdrawal,1,4,50.0\",4,1,insufficient_funds,insufficient available funds
+"
+    );
+}
+
+/*
+    The record column holds the row as csv, a field with a comma or a quote in it is quoted again
+    so the record can be split back into the fields that were read
+ */
+#[test]
+fn test_rejected_record_is_csv_encoded() {
+    let input = "type,client,tx,amount,reason\nadjust,1,1,-5.0,\"fee, \"\"late\"\"\"\n";
+    let rejects_file = tempfile::NamedTempFile::new().unwrap();
+    let rejects_path = rejects_file.path().to_str().unwrap();
+    let mut engine = PaymentsEngine::with_config(EngineConfig { allow_admin: true, ..EngineConfig::default() });
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path).unwrap());
+    engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    let report = fs::read_to_string(rejects_path).unwrap();
+    let mut rejects = csv::Reader::from_reader(report.as_bytes());
+    let rejected = rejects.records().next().unwrap().unwrap();
+    let record = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(rejected[2].as_bytes())
+        .records()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(record, vec!["adjust", "1", "1", "-5.0", "fee, \"late\""]);
+}
+
+/*
+    Illegal steps in the dispute lifecycle are rejected with a specific error and leave the
+    balances alone. Each case runs the rows after a deposit of 10 with tx 1.
+   I.    Disputing the same tx twice only holds the funds once
+   II.   A resolved tx can't be disputed again
+   III.  A charged back tx can't be resolved
+   IV.   A tx that was never disputed can't be charged back
+   V.    A resolved tx can't be charged back
+ */
+#[test_case("dispute,1,1,\ndispute,1,1,", EngineError::AlreadyDisputed { tx: 1 }, "0", "10")]
+#[test_case("dispute,1,1,\nresolve,1,1,\ndispute,1,1,", EngineError::IllegalTransition { tx: 1, from: TxState::Resolved, to: TxState::Disputed }, "10", "0")]
+#[test_case("dispute,1,1,\nchargeback,1,1,\nresolve,1,1,", EngineError::AccountLocked { client: 1 }, "0", "0")]
+#[test_case("chargeback,1,1,", EngineError::NotDisputed { tx: 1 }, "10", "0")]
+#[test_case("dispute,1,1,\nresolve,1,1,\nchargeback,1,1,", EngineError::IllegalTransition { tx: 1, from: TxState::Resolved, to: TxState::ChargedBack }, "10", "0")]
+fn test_dispute_lifecycle(rows: &str, expected: EngineError, available: &str, held: &str) {
+    let input = format!("type,client,tx,amount\ndeposit,1,1,10.0\n{}\n", rows);
+    let rejects_file = tempfile::NamedTempFile::new().unwrap();
+    let rejects_path = rejects_file.path().to_str().unwrap();
+    let mut engine = PaymentsEngine::new();
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path).unwrap());
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(summary.rows_rejected, 1);
+    assert_eq!(summary.rejections.get(expected.code()), Some(&1));
+    assert!(std::fs::read_to_string(rejects_path).unwrap().contains(&expected.to_string()));
+    let client = engine.clients().get(&1).unwrap();
+    assert_eq!(client.available(), available.parse::<Money>().unwrap());
+    assert_eq!(client.held(), held.parse::<Money>().unwrap());
+}
+
+#[test]
+fn test_ledger_entry_transitions() {
+    let mut entry = LedgerEntry {
+        client: 1,
+        kind: EntryKind::Deposit,
+        amount: "1".parse().unwrap(),
+        state: TxState::Processed,
+    };
+    assert_eq!(entry.check_transition(1, TxState::Disputed), Ok(()));
+    entry.state = TxState::ChargedBack;
+    assert_eq!(
+        entry.check_transition(1, TxState::Disputed),
+        Err(EngineError::IllegalTransition { tx: 1, from: TxState::ChargedBack, to: TxState::Disputed })
+    );
+}
+
+/*
+    With disputes allowed to drive available negative, a client who withdrew a deposit can no
+    longer escape the dispute. A chargeback leaves the whole account negative and the output csv
+    keeps the signs.
+ */
+#[test]
+fn test_negative_balance_output() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,100.0
+withdrawal,1,2,80.5
+dispute,1,1,
+chargeback,1,1,
+";
+    let engine = PaymentsEngine::with_config(EngineConfig {
+        dispute_policy: DisputePolicy::AllowNegative,
+        ..EngineConfig::default()
+    });
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+    assert_eq!(summary.rows_rejected, 0);
+
+    let mut output = Vec::new();
+    write_accounts(&engine, &OutputOptions::default(), &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,-80.5000,0.0000,-80.5000,true\n"
+    );
+}
+
+/*
+    i.   By client id, the default
+    ii.  Largest total first, clients 2 and 4 tie and stay in id order
+    iii. The locked client first, the rest in id order
+ */
+#[test_case(SortOrder::ClientId, &[1, 2, 3, 4])]
+#[test_case(SortOrder::Total, &[3, 2, 4, 1])]
+#[test_case(SortOrder::LockedFirst, &[4, 1, 2, 3])]
+fn test_output_sort_order(sort: SortOrder, expected: &[u16]) {
+    let input = "\
+type,client,tx,amount
+deposit,3,1,30.0
+deposit,1,2,1.0
+deposit,4,3,5.0
+deposit,4,4,20.0
+deposit,2,5,20.0
+dispute,4,3,
+chargeback,4,3,
+";
+    let engine = PaymentsEngine::new();
+    engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    let mut output = Vec::new();
+    write_accounts(&engine, &OutputOptions { sort, ..OutputOptions::default() }, &mut output).unwrap();
+    let order: Vec<u16> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|row| row.split(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(order, expected);
+}
+
+/*
+    The same input gives byte-identical output, however many threads processed it
+ */
+#[test]
+fn test_output_is_deterministic() {
+    let input = generate_rows(2_000, 300, 11);
+    let mut outputs = Vec::new();
+    for threads in [1, 1, 4] {
+        let engine = PaymentsEngine::with_config(EngineConfig { threads, ..EngineConfig::default() });
+        engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+        let mut output = Vec::new();
+        write_accounts(&engine, &OutputOptions::default(), &mut output).unwrap();
+        outputs.push(output);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], outputs[2]);
+}
+
+/*
+    A tx id belongs to the first deposit or withdrawal that used it, even when that row could not
+    be applied. The failed deposit reserves id 1, the second deposit is a duplicate and the
+    dispute finds nothing it can hold.
+ */
+#[test]
+fn test_failed_row_claims_tx_id() {
+    let input = "\
+type,client,tx,amount
+deposit,1,1,
+deposit,2,1,5.0
+deposit,1,2,5.0
+dispute,1,1,
+";
+    let engine = PaymentsEngine::new();
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert_eq!(summary.rejections.get("invalid_amount"), Some(&1));
+    assert_eq!(summary.rejections.get("duplicate_tx"), Some(&1));
+    assert_eq!(summary.rejections.get("illegal_transition"), Some(&1));
+    assert_eq!(engine.clients().get(&1).unwrap().held(), Money::ZERO);
+    assert_eq!(engine.clients().get(&2).unwrap().total(), Money::ZERO);
+}
+
+/*
+    Builds a mixed input for rows rows spread over clients clients. A small linear congruential
+    generator keeps it reproducible. Tx ids are reused across clients now and then and disputes
+    reference recent ids of any client, so the rows exercise every cross client rule.
+ */
+pub(crate) fn generate_rows(rows: usize, clients: u64, seed: u64) -> String {
+    let mut state = seed;
+    let mut next = move |bound: u64| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) % bound
+    };
+    let mut input = String::from("type,client,tx,amount\n");
+    for tx in 1..=rows as u64 {
+        let client = next(clients) + 1;
+        let referenced = tx.saturating_sub(next(64)).max(1);
+        let row = match next(20) {
+            0..=8 => format!("deposit,{},{},{}.{:04}", client, tx, next(1000), next(10_000)),
+            9..=13 => format!("withdrawal,{},{},{}.{:04}", client, tx, next(500), next(10_000)),
+            14 => format!("deposit,{},{},{}.5", client, referenced, next(100)),
+            15..=16 => format!("dispute,{},{},", client, referenced),
+            17..=18 => format!("resolve,{},{},", client, referenced),
+            _ => format!("chargeback,{},{},", client, referenced),
+        };
+        input.push_str(&row);
+        input.push('\n');
+    }
+    input
+}
+
+pub(crate) fn balances(engine: &PaymentsEngine) -> Vec<(u16, Money, Money, Money, bool)> {
+    let mut balances: Vec<_> = engine
+        .clients()
+        .iter()
+        .map(|client| (client.id(), client.available(), client.held(), client.total(), client.locked()))
+        .collect();
+    balances.sort();
+    balances
+}
+
+/*
+    Sharding rows between workers must not change the outcome. Every client ends with the same
+    balances and the same rows are rejected as in a sequential run.
+ */
+#[test_case(2)]
+#[test_case(3)]
+#[test_case(8)]
+fn test_parallel_matches_sequential(threads: usize) {
+    let input = generate_rows(20_000, 40, 11);
+    let sequential = PaymentsEngine::new();
+    let sequential_summary = sequential.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    let parallel = PaymentsEngine::with_config(EngineConfig { threads, ..EngineConfig::default() });
+    let parallel_summary = parallel.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    assert!(sequential_summary.rows_rejected > 0);
+    assert_eq!(parallel_summary, sequential_summary);
+    assert_eq!(balances(&parallel), balances(&sequential));
+}
+
+/*
+    Benchmark behind the numbers in analysis.md, run it with
+        cargo test --release bench_parallel_pipeline -- --ignored --nocapture
+    BENCH_ROWS sets the number of rows, 2,000,000 by default.
+ */
+#[test]
+#[ignore]
+fn bench_parallel_pipeline() {
+    let rows = std::env::var("BENCH_ROWS").ok().and_then(|rows| rows.parse().ok()).unwrap_or(2_000_000);
+    let input = generate_rows(rows, 1_000, 7);
+    let mut expected = None;
+    for threads in [1, 2, 4, 8] {
+        let mut runs = Vec::new();
+        for _ in 0..5 {
+            let engine = PaymentsEngine::with_config(EngineConfig { threads, ..EngineConfig::default() });
+            let start_time = std::time::Instant::now();
+            engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+            runs.push(start_time.elapsed().as_secs_f64());
+            assert_eq!(balances(&engine), *expected.get_or_insert_with(|| balances(&engine)));
+        }
+        let mean = runs.iter().sum::<f64>() / runs.len() as f64;
+        let deviation = (runs.iter().map(|run| (run - mean).powi(2)).sum::<f64>() / runs.len() as f64).sqrt();
+        println!("{} rows, {} threads: mean {:.3} s, standard deviation {:.3} s", rows, threads, mean, deviation);
+    }
+}
+
+/*
+    Operations unlocks an account after a chargeback was settled and corrects it, after that the
+    client can deposit again. Without --allow-admin the same rows are refused and reported.
+ */
+#[test_case(true, "12", false, 0)]
+#[test_case(false, "5", true, 2)]
+fn test_admin_rows(allow_admin: bool, total: &str, locked: bool, unauthorized: usize) {
+    let input = "\
+type,client,tx,amount,reason
+deposit,1,1,10.0,
+deposit,1,2,5.0,
+dispute,1,1,,
+chargeback,1,1,,
+unlock,1,100,,
+adjust,1,101,-1.0,fee reversal
+deposit,1,3,8.0,
+";
+    let dir = tempfile::tempdir().unwrap();
+    let rejects_path = dir.path().join("rejects.csv");
+    let mut engine = PaymentsEngine::with_config(EngineConfig { allow_admin, ..EngineConfig::default() });
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path.to_str().unwrap()).unwrap());
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+
+    let client = engine.clients().get(&1).unwrap();
+    assert_eq!(client.total(), total.parse::<Money>().unwrap());
+    assert_eq!(client.locked(), locked);
+    assert_eq!(summary.rejections.get("unauthorized").copied().unwrap_or(0), unauthorized);
+    let rejects = std::fs::read_to_string(rejects_path).unwrap();
+    assert_eq!(rejects.matches(",unauthorized,").count(), unauthorized);
+}
+
+/*
+    A client whose balances no longer add up stops the run at the first row that touches it,
+    the error and the rejects report name the tx
+ */
+#[test_case(1)]
+#[test_case(2)]
+fn test_invariant_violation_stops_run(threads: usize) {
+    let dir = tempfile::tempdir().unwrap();
+    let rejects_path = dir.path().join("rejects.csv");
+    let mut engine = PaymentsEngine::with_config(EngineConfig { threads, check_invariants: true, ..EngineConfig::default() });
+    engine.set_rejects_writer(RejectsWriter::from_path(rejects_path.to_str().unwrap()).unwrap());
+    engine.process_transactions_from_reader("type,client,tx,amount\ndeposit,1,1,10.0\n".as_bytes()).unwrap();
+    engine.clients().get_mut(&1).unwrap().set_held("4".parse().unwrap()).unwrap();
+
+    let result = engine.process_transactions_from_reader("type,client,tx,amount\ndeposit,2,2,1.0\ndeposit,1,3,1.0\n".as_bytes());
+    assert_eq!(
+        result,
+        Err(EngineError::InvariantViolation { tx: 3, client: 1, reason: "held 4.0000 != open disputes 0.0000".to_string() })
+    );
+    drop(engine);
+    assert!(std::fs::read_to_string(rejects_path).unwrap().contains("3,1,invariant_violation"));
+}
+
+/*
+    The engine embedded as a library: transactions are built in code and applied one at a time,
+    with the same checks as rows read from a file, and the accounts are read back as copies.
+ */
+#[test]
+fn test_apply_built_transactions() {
+    let engine = crate::Engine::new();
+    let amount = |value: &str| value.parse::<Money>().unwrap();
+    engine.apply(&Transaction::builder(TransactionKind::Deposit, 2, 1).amount(amount("10")).build()).unwrap();
+    engine.apply(&Transaction::builder(TransactionKind::Deposit, 1, 2).amount(amount("4.5")).build()).unwrap();
+    engine.apply(&Transaction::builder(TransactionKind::Dispute, 2, 1).build()).unwrap();
+
+    assert_eq!(
+        engine.apply(&Transaction::builder(TransactionKind::Withdrawal, 2, 1).amount(amount("1")).build()),
+        Err(EngineError::DuplicateTx { tx: 1 })
+    );
+    assert_eq!(
+        engine.apply(&Transaction::builder(TransactionKind::Dispute, 3, 2).build()),
+        Err(EngineError::ClientMismatch { tx: 2, owner: 1, client: 3 })
+    );
+    assert_eq!(
+        engine.apply(&Transaction::builder(TransactionKind::Lock, 1, 9).build()),
+        Err(EngineError::Unauthorized { kind: TransactionKind::Lock })
+    );
+
+    let client = engine.client(2).unwrap();
+    assert_eq!((client.available(), client.held(), client.total()), (Money::ZERO, amount("10"), amount("10")));
+    assert!(engine.client(4).is_none());
+    let ids: Vec<u16> = engine.client_list().iter().map(|client| client.id()).collect();
+    assert_eq!(ids, [1, 2, 3]);
+}
+
+/*
+    i.   Lower case
+    ii.  Any case
+    iii. Surrounding spaces
+    iv.  Admin rows
+    v.   Unknown kinds are unsupported
+ */
+#[test_case("deposit", Ok(TransactionKind::Deposit))]
+#[test_case("ChargeBack", Ok(TransactionKind::Chargeback))]
+#[test_case(" Withdrawal ", Ok(TransactionKind::Withdrawal))]
+#[test_case("UNLOCK", Ok(TransactionKind::Unlock))]
+#[test_case("refund", Err(EngineError::UnsupportedType { transaction_type: "refund".to_string() }))]
+fn test_transaction_kind(name: &str, expected: Result<TransactionKind, EngineError>) {
+    assert_eq!(name.parse::<TransactionKind>(), expected);
+    let deserialized = serde_json::from_value::<TransactionKind>(serde_json::Value::from(name));
+    assert_eq!(deserialized.ok(), expected.clone().ok());
+    if let Ok(kind) = expected {
+        assert_eq!(Transaction::builder(kind, 1, 1).build().kind(), kind);
+        assert_eq!(kind.to_string().parse::<TransactionKind>(), Ok(kind));
+    }
+}
+
+/*
+    The type column is matched without regard to case, a "Dispute" row holds the deposit like a
+    "dispute" row would. A row of an unknown kind is rejected when it is read, so client 2 is never
+    created.
+ */
+#[test]
+fn test_mixed_case_types() {
+    let input = "\
+type,client,tx,amount
+Deposit,1,1,10.0
+DEPOSIT,1,2,5.0
+Dispute,1,1,
+ChargeBack,1,1,
+Refund,2,3,1.0
+";
+    let engine = PaymentsEngine::new();
+    let summary = engine.process_transactions_from_reader(input.as_bytes()).unwrap();
+    assert_eq!(summary.rows_rejected, 1);
+    assert_eq!(summary.rejections.get("unsupported_type"), Some(&1));
+
+    let client = engine.client(1).unwrap();
+    assert_eq!(client.total(), "5".parse::<Money>().unwrap());
+    assert!(client.locked());
+    assert!(engine.client(2).is_none());
+}
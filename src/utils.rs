@@ -1,29 +1,229 @@
-use clap::{Arg, Command};
-use std::error::Error;
-use csv::Writer;
-use crate::client::Client;
-use dashmap::DashMap;
-use std::io;
-use rayon::prelude::*;
-use std::time::Instant;
-use log::{info};
+use clap::{Arg, ArgAction, Command};
+use accountant::{
+    AmountRules, DisputePolicy, DuplicatePolicy, EngineConfig, OutputFormat, OutputOptions, PrecisionPolicy, SortOrder,
+};
 
-pub fn parse_cli_arguments() -> String {
+// Input argument that tells the program to read transactions from stdin
+pub const STDIN_INPUT: &str = "-";
+
+/*
+    Everything the program was asked to do on the command line
+ */
+#[derive(Debug)]
+pub struct CliArgs {
+    // Input files, directories and globs as given, expanded by inputs::expand_inputs
+    pub inputs: Vec<String>,
+    pub config: EngineConfig,
+    pub rejects: Option<String>,
+    pub adjustments: Option<String>,
+    pub history_file: Option<String>,
+    pub load_state: Option<String>,
+    pub save_state: Option<String>,
+    pub journal: Option<String>,
+    pub output: OutputOptions,
+}
+
+pub fn parse_cli_arguments() -> CliArgs {
     let matches = Command::new("Santas_amex")
         .version("1.0")
         .about("Processes Santa's toy purchases from a CSV file")
         .arg(
             Arg::new("input")
-                .help("Path to the input CSV file")
-                .required(true)
+                .help("Input CSV files, directories or globs processed in order against the same ledger, use - or leave out to read from stdin")
+                .num_args(1..)
+                .default_value(STDIN_INPUT)
                 .index(1),
         )
+        .arg(
+            Arg::new("duplicates")
+                .long("duplicates")
+                .help("What to do with a deposit or withdrawal that reuses a tx id")
+                .value_parser(DuplicatePolicy::VARIANTS)
+                .default_value("reject"),
+        )
+        .arg(
+            Arg::new("dispute-policy")
+                .long("dispute-policy")
+                .help("Whether a dispute may drive a client's available balance negative")
+                .value_parser(DisputePolicy::VARIANTS)
+                .default_value("reject"),
+        )
+        .arg(
+            Arg::new("allow-admin")
+                .long("allow-admin")
+                .help("Accept lock, unlock and adjust rows, only for input from operations")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("check-invariants")
+                .long("check-invariants")
+                .help("Check every client's balances after each row, always on in debug builds")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .value_name("N")
+                .help("Number of worker threads, rows are sharded between them by client")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1"),
+        )
+        .arg(
+            Arg::new("max-decimals")
+                .long("max-decimals")
+                .value_name("PLACES")
+                .help("Decimal places an input amount may have")
+                .value_parser(clap::value_parser!(u32).range(0..=4))
+                .default_value("4"),
+        )
+        .arg(
+            Arg::new("allow-exponent")
+                .long("allow-exponent")
+                .help("Accept amounts written in exponent notation such as 1.5e2")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("excess-precision")
+                .long("excess-precision")
+                .help("What to do with an amount that has more decimal places than allowed")
+                .value_parser(PrecisionPolicy::VARIANTS)
+                .default_value("reject"),
+        )
+        .arg(
+            Arg::new("rejects")
+                .long("rejects")
+                .value_name("FILE")
+                .help("Write every row that was not applied to this csv file"),
+        )
+        .arg(
+            Arg::new("adjustments")
+                .long("adjustments")
+                .value_name("FILE")
+                .help("Write every amount that was rounded or truncated to this csv file"),
+        )
+        .arg(
+            Arg::new("history-file")
+                .long("history-file")
+                .value_name("FILE")
+                .help("Keep the transaction history in this scratch file instead of memory"),
+        )
+        .arg(
+            Arg::new("load-state")
+                .long("load-state")
+                .value_name("FILE")
+                .help("Start from the balances and history saved by an earlier run"),
+        )
+        .arg(
+            Arg::new("save-state")
+                .long("save-state")
+                .value_name("FILE")
+                .help("Save the balances and history at the end of the run for the next one"),
+        )
+        .arg(
+            Arg::new("journal")
+                .long("journal")
+                .value_name("FILE")
+                .help("Journal every row before it is applied, rerun with the same journal to recover"),
+        )
+        .arg(
+            Arg::new("output-format")
+                .long("output-format")
+                .help("Format of the accounts written to stdout")
+                .value_parser(OutputFormat::VARIANTS)
+                .default_value("csv"),
+        )
+        .arg(
+            Arg::new("include-disputes")
+                .long("include-disputes")
+                .help("Add each client's open disputes to the output")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("Order of the accounts in the output")
+                .value_parser(SortOrder::VARIANTS)
+                .default_value("client"),
+        )
+        .arg(
+            Arg::new("precision")
+                .long("precision")
+                .value_name("PLACES")
+                .help("Decimal places of the amounts in the output")
+                .value_parser(clap::value_parser!(u32).range(0..=18))
+                .default_value("4"),
+        )
+        .arg(
+            Arg::new("trim-zeros")
+                .long("trim-zeros")
+                .help("Drop trailing zeros of the amounts in the output, 5.5000 is written as 5.5")
+                .action(ArgAction::SetTrue),
+        )
         .get_matches();
 
-    matches
-        .get_one::<String>("input")
-        .expect("CSV file is needed to check Santa's transactions")
-        .clone()
+    let inputs = matches
+        .get_many::<String>("input")
+        .expect("input always has a default value")
+        .cloned()
+        .collect();
+    let duplicate_policy = matches
+        .get_one::<String>("duplicates")
+        .and_then(|policy| policy.parse().ok())
+        .unwrap_or_default();
+    let dispute_policy = matches
+        .get_one::<String>("dispute-policy")
+        .and_then(|policy| policy.parse().ok())
+        .unwrap_or_default();
+    let format = matches
+        .get_one::<String>("output-format")
+        .and_then(|format| format.parse().ok())
+        .unwrap_or_default();
+    let sort = matches
+        .get_one::<String>("sort")
+        .and_then(|sort| sort.parse().ok())
+        .unwrap_or_default();
+    let precision = *matches
+        .get_one::<u32>("precision")
+        .expect("precision always has a default value");
+    let max_decimal_places = *matches
+        .get_one::<u32>("max-decimals")
+        .expect("max-decimals always has a default value");
+    let excess_precision = matches
+        .get_one::<String>("excess-precision")
+        .and_then(|policy| policy.parse().ok())
+        .unwrap_or_default();
+    let threads = *matches
+        .get_one::<usize>("threads")
+        .expect("threads always has a default value");
+
+    CliArgs {
+        inputs,
+        config: EngineConfig {
+            duplicate_policy,
+            dispute_policy,
+            threads,
+            allow_admin: matches.get_flag("allow-admin"),
+            check_invariants: matches.get_flag("check-invariants"),
+            amounts: AmountRules {
+                max_decimal_places,
+                allow_exponent: matches.get_flag("allow-exponent"),
+                excess_precision,
+            },
+        },
+        rejects: matches.get_one::<String>("rejects").cloned(),
+        adjustments: matches.get_one::<String>("adjustments").cloned(),
+        history_file: matches.get_one::<String>("history-file").cloned(),
+        load_state: matches.get_one::<String>("load-state").cloned(),
+        save_state: matches.get_one::<String>("save-state").cloned(),
+        journal: matches.get_one::<String>("journal").cloned(),
+        output: OutputOptions {
+            format,
+            sort,
+            precision: precision as usize,
+            trim_zeros: matches.get_flag("trim-zeros"),
+            include_disputes: matches.get_flag("include-disputes"),
+        },
+    }
 }
 
 pub fn setup_logger(log_file: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -42,35 +242,6 @@ pub fn setup_logger(log_file: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-pub fn write_clients_to_csv(client_map: &DashMap<u16, Client>) -> Result<(), Box<dyn Error>> {
-    let start_time = Instant::now();
-    let mut writer = Writer::from_writer(io::stdout());
-
-    // Write the header row
-    writer.write_record(&["client", " available", " held", " total", " locked"])?;
-
-    // Write each client's data as a row
-    for client_entry in client_map {
-        let client = client_entry.value();
-        writer.write_record(&[
-            client.id().to_string(),
-            format!(" {:.4}", client.available()),
-            format!(" {:.4}", client.held()),
-            format!(" {:.4}", client.total()),
-            format!(" {}", client.locked().to_string()),
-        ])?;
-    }
-
-    writer.flush()?;
-    let elapsed_time = start_time.elapsed();
-        info!(
-            "write_clients_to_csv completed in {:.2?} seconds.",
-            elapsed_time
-        );
-    Ok(())
-}
-
-
 /* NEW: Using multi-threading to increase speed of writing the output to csv. A cost of
         this function is the Dashmap must be converted into Vec which operates at O(n) and
         could become a slog if given a large enough dataset.
@@ -81,7 +252,7 @@ pub fn write_clients_to_csv(client_map: &DashMap<u16, Client>) -> Result<(), Box
        // function on a dashmap
 //     let clients: Vec<_> = client_map.iter().collect();
 //     let mut writer = Writer::from_writer(io::stdout());
-//     writer.write_record(&["client", "available", "held", "total", "locked"])?;
+//     writer.write_record(["client", "available", "held", "total", "locked"])?;
 //
 //     // Using par_iter to write the rows in chunks. This should open additional threads to complete
 //     // the  task and increase speed